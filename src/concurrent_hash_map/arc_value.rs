@@ -0,0 +1,225 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+use crate::concurrent_hash_map::base::ConcurrentHashMap;
+use crate::concurrent_hash_map::map::ConcurrentMap;
+use crate::ebr::collector::Guard;
+
+impl<K, V, S> ConcurrentHashMap<K, Arc<V>, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    /// Returns a clone of the `Arc` to which the specified key is mapped, or `None` if this
+    /// map contains no mapping for the key. See `get_cloned`.
+    pub fn get_arc<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_cloned(key)
+    }
+}
+
+/// A concurrent map backed by a `ConcurrentHashMap` storing every value in an `Arc`.
+///
+/// Reads, and the values replaced or removed, are returned as clones of the `Arc` instead of
+/// `Value`s. They do not borrow the map nor keep its collector pinned, so they can be held
+/// for long, for example across await points, without holding back the reclamation of other
+/// values. The cost is an allocation per value, and a reference count update per read.
+pub struct ArcValueMap<K, V, S = RandomState> {
+    map: ConcurrentHashMap<K, Arc<V>, S>,
+}
+
+impl<K, V> ArcValueMap<K, V, RandomState>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn new() -> ArcValueMap<K, V> {
+        Self::with_hasher(RandomState::new())
+    }
+    /// Creates an empty map with an initial table sized to accommodate `capacity` elements
+    /// without resizing.
+    pub fn with_capacity(capacity: usize) -> ArcValueMap<K, V> {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K, V, S> Default for ArcValueMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> From<ConcurrentHashMap<K, Arc<V>, S>> for ArcValueMap<K, V, S> {
+    fn from(map: ConcurrentHashMap<K, Arc<V>, S>) -> Self {
+        Self { map }
+    }
+}
+
+impl<K, V, S> ArcValueMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self {
+            map: ConcurrentHashMap::with_hasher(hash_builder),
+        }
+    }
+    /// Creates an empty map which will use `hash_builder` to hash keys, with an initial table
+    /// sized to accommodate `capacity` elements without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: ConcurrentHashMap::with_capacity_and_hasher(capacity, hash_builder),
+        }
+    }
+    /// Returns the map backing this one.
+    pub fn map(&self) -> &ConcurrentHashMap<K, Arc<V>, S> {
+        &self.map
+    }
+    /// Consumes this map, returning the map backing it.
+    pub fn into_inner(self) -> ConcurrentHashMap<K, Arc<V>, S> {
+        self.map
+    }
+    /// Pins the collector of the backing map, see `ConcurrentHashMap::guard`.
+    pub fn guard(&self) -> Guard<'_> {
+        self.map.guard()
+    }
+    /// Maps `key` to an `Arc` which may be shared with other maps or threads.
+    /// Returns the previous value.
+    pub fn insert_arc(&self, key: K, value: Arc<V>) -> Option<Arc<V>> {
+        self.map.insert(key, value).map(|v| Arc::clone(&v))
+    }
+}
+
+impl<K, V, S> ConcurrentMap<K, V> for ArcValueMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    type Ref<'a>
+        = Arc<V>
+    where
+        Self: 'a;
+
+    fn size(&self) -> usize {
+        self.map.size()
+    }
+    fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let guard = self.map.guard();
+        let found = self.map.iter(&guard).any(|(_, v)| **v == *value);
+        found
+    }
+    fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.get_arc(key)
+    }
+    fn insert(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.insert_arc(key, Arc::new(value))
+    }
+    fn put_if_absent(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.map
+            .put_if_absent(key, Arc::new(value))
+            .map(|v| Arc::clone(&v))
+    }
+    fn remove<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.remove(key).map(|v| Arc::clone(&v))
+    }
+    fn remove_entry<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        let guard = self.map.guard();
+        unsafe {
+            self.map
+                .replace_node(key, None, None, |v| **v == *value, &guard)
+                .map(|old| guard.defer_destroy(old))
+                .is_some()
+        }
+    }
+    fn replace<Q>(&self, key: &Q, old_value: &V, new_value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        let guard = self.map.guard();
+        let new_value = Some(Arc::new(new_value));
+        unsafe {
+            self.map
+                .replace_node(key, None, new_value, |v| **v == *old_value, &guard)
+                .map(|old| guard.defer_destroy(old))
+                .is_some()
+        }
+    }
+    fn compute_if_absent<F>(&self, key: K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&K) -> Option<V>,
+    {
+        self.map
+            .compute_if_absent(key, |k| f(k).map(Arc::new))
+            .map(|v| Arc::clone(&v))
+    }
+    fn compute_if_present<F>(&self, key: &K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&K, &V) -> Option<V>,
+    {
+        self.map
+            .compute_if_present(key, |k, v| f(k, v).map(Arc::new))
+            .map(|v| Arc::clone(&v))
+    }
+    fn compute<F>(&self, key: K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&K, Option<&V>) -> Option<V>,
+    {
+        self.map
+            .compute(key, |k, v| f(k, v.map(|v| &**v)).map(Arc::new))
+            .map(|v| Arc::clone(&v))
+    }
+    fn merge<F>(&self, key: K, value: V, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&V, V) -> Option<V>,
+    {
+        self.compute(key, |_, old| match old {
+            None => Some(value),
+            Some(old) => f(old, value),
+        })
+    }
+    fn clear(&self) {
+        self.map.clear();
+    }
+    fn for_each_entry<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        let guard = self.map.guard();
+        for (k, v) in self.map.iter(&guard) {
+            f(k, v);
+        }
+    }
+}
@@ -1,40 +1,107 @@
 use std::{panic, ptr, thread};
+use std::borrow::Borrow;
+use std::cell::RefCell;
 use std::collections::hash_map::RandomState;
 use std::hash::{BuildHasher, Hash};
 use std::hint::spin_loop;
+use std::iter;
+use std::mem::ManuallyDrop;
+use std::panic::AssertUnwindSafe;
 use std::sync::Once;
 use std::sync::atomic::{AtomicIsize, AtomicPtr, Ordering};
 
-use parking_lot::Mutex;
+use parking_lot::{Mutex, MutexGuard};
 
+use crate::concurrent_hash_map::bulk::{Executor, ScopedThreads};
+use crate::concurrent_hash_map::entry::{Entry, OccupiedEntry, Slot, Tail, VacantEntry};
 use crate::concurrent_hash_map::forwarding::ForwardingNode;
-use crate::concurrent_hash_map::map::{Map, Value};
+use crate::concurrent_hash_map::iter::{Iter, Keys, Traverser, Values};
+use crate::concurrent_hash_map::map::{ConcurrentMap, Value};
 use crate::concurrent_hash_map::node::Node;
-use crate::concurrent_hash_map::tree::{TreeBin, TreeNode};
-use crate::ebr::collector::{Collector, Guard};
+use crate::concurrent_hash_map::reservation::ReservationNode;
+use crate::concurrent_hash_map::stats::Counters;
+use crate::concurrent_hash_map::tree::{KeyCmp, KeyOrd, TreeBin, TreeNode};
+use crate::ebr::collector::{Collector, Guard, Reclaim, Unprotected};
 
 pub(crate) struct BaseNode<K, V> {
     pub(crate) node: AtomicPtr<NodeEnums<K, V>>,
-    pub(crate) k: AtomicPtr<u32>,
-    lock: Mutex<()>,
+    pub(crate) lock: Mutex<()>,
+}
+
+/// A bin locked by `lock_bin`. The lock is released when this is dropped.
+pub(crate) struct LockedBin<'a, K, V> {
+    pub(crate) tab: &'a [BaseNode<K, V>],
+    pub(crate) index: usize,
+    pub(crate) node: *mut NodeEnums<K, V>,
+    pub(crate) lock: MutexGuard<'a, ()>,
 }
 
 pub(crate) enum NodeEnums<K, V> {
     Node(Node<K, V>),
     ForwardingNode(ForwardingNode<K, V>),
     TreeBin(TreeBin<K, V>),
+    ReservationNode(ReservationNode),
 }
 
 impl<K, V> NodeEnums<K, V> {
     fn is_moved(&self) -> bool {
-        match self {
-            NodeEnums::ForwardingNode(_) => true,
-            _ => false,
-        }
+        matches!(self, NodeEnums::ForwardingNode(_))
     }
-    fn into_box(self) -> *mut NodeEnums<K, V> {
+    pub(crate) fn into_box(self) -> *mut NodeEnums<K, V> {
         Box::into_raw(Box::new(self))
     }
+    /// The tree bin `p` points to, borrowed mutably for an update under its bin lock. Heads of
+    /// linked bins are only ever borrowed shared, since readers traverse them without the lock.
+    pub(crate) unsafe fn tree_bin_mut<'a>(p: *mut NodeEnums<K, V>) -> &'a mut TreeBin<K, V> {
+        match &mut *p {
+            NodeEnums::TreeBin(t) => t,
+            _ => unreachable!("not a tree bin"),
+        }
+    }
+    /// Reclaims a bin that is no longer reachable from its table: the bin itself, every node
+    /// chained to it, and all of their keys and values.
+    /// Returns the number of entries it held.
+    unsafe fn reclaim_bin<R: Reclaim>(p: *mut NodeEnums<K, V>, r: &R) -> isize {
+        let mut count = 0;
+        let mut e = match &*p {
+            NodeEnums::Node(head) => {
+                count += 1;
+                r.reclaim(head.val.load(Ordering::Acquire));
+                head.next.load(Ordering::Acquire)
+            }
+            NodeEnums::TreeBin(t) => t.first.load(Ordering::Acquire),
+            NodeEnums::ForwardingNode(_) | NodeEnums::ReservationNode(_) => ptr::null_mut(),
+        };
+        while let Some(node) = e.as_ref() {
+            count += 1;
+            let next = node.next.load(Ordering::Acquire);
+            r.reclaim(node.val.load(Ordering::Acquire));
+            Node::retire(e, r);
+            e = next;
+        }
+        Self::retire(p, r);
+        count
+    }
+    /// Reclaims a bin that was removed from the map, together with the key of the node it
+    /// holds, if any. See `Node::retire`.
+    pub(crate) unsafe fn retire<R: Reclaim>(p: *mut NodeEnums<K, V>, r: &R) {
+        r.reclaim_with(move || {
+            if let NodeEnums::Node(head) = &mut *Box::from_raw(p) {
+                head.drop_key();
+            }
+        })
+    }
+}
+
+/// Which keys a remapping function passed to `compute_val` is called for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Remap {
+    /// Only absent keys, present values are returned as they are.
+    Absent,
+    /// Only present keys, absent keys stay absent.
+    Present,
+    /// Both present and absent keys.
+    Always,
 }
 
 impl<K, V> BaseNode<K, V>
@@ -44,10 +111,33 @@ where
     fn new() -> BaseNode<K, V> {
         Self {
             lock: Mutex::new(()),
-            k: AtomicPtr::default(),
             node: AtomicPtr::default(),
         }
     }
+    /// Locks this bin. A remapping function that updates a mapping in the bin it runs under
+    /// would wait on its own lock forever, so this panics with "Recursive update" instead, like
+    /// the `IllegalStateException` of the JDK.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ()> {
+        if let Some(lock) = self.lock.try_lock() {
+            return lock;
+        }
+        let bin = self as *const Self as usize;
+        if COMPUTING.with(|c| c.borrow().contains(&bin)) {
+            panic!("Recursive update");
+        }
+        self.lock.lock()
+    }
+}
+
+impl<K, V> Drop for BaseNode<K, V> {
+    fn drop(&mut self) {
+        // a transferred table only holds forwarding nodes here, the bins of a live table are
+        // reclaimed and emptied before it is dropped
+        let p = *self.node.get_mut();
+        if !p.is_null() {
+            unsafe { drop(Box::from_raw(p)) };
+        }
+    }
 }
 
 /// The largest possible table capacity.
@@ -57,79 +147,105 @@ where
 const MAXIMUM_CAPACITY: usize = 1 << (isize::BITS - 2);
 /// The default initial table capacity. Must be a power of 2 (i.e., at least 1) and at most
 /// MAXIMUM_CAPACITY.
-const DEFAULT_CAPACITY: usize = 16;
+pub(crate) const DEFAULT_CAPACITY: usize = 16;
 /// The largest possible (non-power of two) array size. Needed by toArray and related methods.
+#[allow(dead_code)]
 const MAX_ARRAY_SIZE: isize = isize::MAX;
 /// The load factor for this table.
 /// Overrides of this value in constructors affect only the initial table capacity.
 /// The actual floating point value isn't normally used -- it is simpler to use expressions such
 /// as n - (n >>> 2) for the associated resizing threshold.
-const LOAD_FACTOR: f32 = 0.75;
+pub(crate) const LOAD_FACTOR: f32 = 0.75;
 /// The bin count threshold for using a tree rather than list for a bin.
 /// Bins are converted to trees when adding an element to a bin with at least this many nodes.
 /// The value must be greater than 2, and should be at least 8 to mesh with assumptions in tree
 /// removal about conversion back to plain bins upon shrinkage.
-const TREEIFY_THRESHOLD: usize = 8;
+pub(crate) const TREEIFY_THRESHOLD: usize = 8;
 /// The bin count threshold for untreeifying a (split) bin during a resize operation.
 /// Should be less than TREEIFY_THRESHOLD, and at most 6 to mesh with shrinkage detection under removal.
-const UNTREEIFY_THRESHOLD: usize = 6;
+pub(crate) const UNTREEIFY_THRESHOLD: usize = 6;
 /// The smallest table capacity for which bins may be treeified.
 /// (Otherwise the table is resized if too many nodes in a bin.) The value should be at least
 /// 4 * TREEIFY_THRESHOLD to avoid conflicts between resizing and treeification thresholds.
-const MIN_TREEIFY_CAPACITY: usize = 64;
+pub(crate) const MIN_TREEIFY_CAPACITY: usize = 64;
 /// Minimum number of rebinnings per transfer step. Ranges are subdivided to allow multiple
 /// resizer threads. This value serves as a lower bound to avoid resizers encountering excessive
 /// memory contention. The value should be at least DEFAULT_CAPACITY.
-const MIN_TRANSFER_STRIDE: isize = 16;
+pub(crate) const MIN_TRANSFER_STRIDE: isize = 16;
 /// The number of bits used for generation stamp in sizeCtl. Must be at least 6 for 32bit arrays.
 const RESIZE_STAMP_BITS: isize = 16;
 /// The maximum number of threads that can help resize. Must fit in 32 - RESIZE_STAMP_BITS bits.
-const MAX_RESIZERS: isize = (1 << (usize::BITS as isize - RESIZE_STAMP_BITS)) - 1;
+pub(crate) const MAX_RESIZERS: isize = (1 << (usize::BITS as isize - RESIZE_STAMP_BITS)) - 1;
 /// The bit shift for recording size stamp in sizeCtl.
 const RESIZE_STAMP_SHIFT: isize = isize::BITS as isize - RESIZE_STAMP_BITS;
 
 /// Encodings for Node hash fields. See above for explanation.
 /// hash for forwarding nodes
+#[allow(dead_code)]
 const MOVED: usize = -1isize as usize;
 /// hash for roots of trees
+#[allow(dead_code)]
 const TREEBIN: usize = -2isize as usize;
-/// hash for transient reservations
-const RESERVED: usize = -3isize as usize;
 /// usable bits of normal node hash
 const HASH_BITS: usize = isize::MAX as usize;
 /// Number of CPUS, to place bounds on some sizings
 static mut NCPU: usize = 0;
-const INIT: Once = Once::new();
+static INIT: Once = Once::new();
+
+thread_local! {
+    /// The bins locked by this thread while it runs a remapping function, see `BaseNode::lock`.
+    static COMPUTING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The number of times this thread updated the count of a map, see `add_count`.
+    pub(crate) static COUNT_UPDATES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
 
 pub struct ConcurrentHashMap<K, V, S = RandomState> {
-    collector: Collector,
+    pub(crate) collector: Collector,
     hash_builder: S,
     // The array of bins. Lazily initialized upon first insertion. Size is always a power of two.
     // Accessed directly by iterators.
-    table: AtomicPtr<Box<[BaseNode<K, V>]>>,
+    pub(crate) table: AtomicPtr<Box<[BaseNode<K, V>]>>,
     // The next table to use; non-null only while resizing.
-    next_table: AtomicPtr<Box<[BaseNode<K, V>]>>,
+    pub(crate) next_table: AtomicPtr<Box<[BaseNode<K, V>]>>,
     // Base counter value, used mainly when there is no contention,
     // but also as a fallback during table initialization races. Updated via CAS.
-    base_count: AtomicIsize,
+    pub(crate) base_count: AtomicIsize,
     // Table initialization and resizing control. When negative, the table is being initialized or resized: -1 for
     // initialization, else -(1 + the number of active resizing threads). Otherwise, when table is null,
     // holds the initial table size to use upon creation, or 0 for default. After initialization,
     // holds the next element count value upon which to resize the table.
-    size_ctl: AtomicIsize,
+    pub(crate) size_ctl: AtomicIsize,
     // The next table index (plus one) to split while resizing.
     transfer_index: AtomicIsize,
     // Spinlock (locked via CAS) used when resizing and/or creating CounterCells.
     cells_busy: AtomicIsize,
     // Table of counter cells. When non-null, size is a power of 2.
-    counter_cells: AtomicPtr<Vec<AtomicIsize>>,
+    pub(crate) counter_cells: AtomicPtr<Vec<AtomicIsize>>,
+    // Order of keys with equal hashes in tree bins, if the map was created with ordered bins.
+    pub(crate) key_cmp: Option<KeyCmp<K>>,
+    // Runs the batches of bulk operations.
+    pub(crate) executor: Box<dyn Executor>,
+    // Per map values of TREEIFY_THRESHOLD, UNTREEIFY_THRESHOLD, MIN_TREEIFY_CAPACITY and
+    // MIN_TRANSFER_STRIDE, which ConcurrentHashMapBuilder may override.
+    pub(crate) treeify_threshold: usize,
+    pub(crate) untreeify_threshold: usize,
+    pub(crate) min_treeify_capacity: usize,
+    pub(crate) min_transfer_stride: isize,
+    // Resize and treeify events, recorded with the stats feature.
+    pub(crate) counters: Counters,
 }
 
-impl<K, V> ConcurrentHashMap<K, V>
+impl<K, V, S> ConcurrentHashMap<K, V, S>
 where
     K: Hash + Eq + Send + 'static,
     V: Send + 'static,
+    S: BuildHasher,
 {
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         unsafe {
             if let Some(option) = self.table.load(Ordering::Acquire).as_ref() {
@@ -139,20 +255,13 @@ where
             }
         }
     }
-    pub fn new() -> ConcurrentHashMap<K, V> {
-        INIT.call_once(|| unsafe {
-            let n = thread::available_parallelism()
-                .map(|v| v.get())
-                .unwrap_or(1);
-            if n == 0 {
-                NCPU = 1;
-            } else {
-                NCPU = n;
-            }
-        });
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    /// The table is allocated lazily upon first insertion.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        init_ncpu();
         Self {
             collector: Collector::new(),
-            hash_builder: RandomState::new(),
+            hash_builder,
             table: Default::default(),
             next_table: Default::default(),
             base_count: Default::default(),
@@ -160,15 +269,403 @@ where
             transfer_index: Default::default(),
             cells_busy: Default::default(),
             counter_cells: Default::default(),
+            key_cmp: None,
+            executor: Box::new(ScopedThreads),
+            treeify_threshold: TREEIFY_THRESHOLD,
+            untreeify_threshold: UNTREEIFY_THRESHOLD,
+            min_treeify_capacity: MIN_TREEIFY_CAPACITY,
+            min_transfer_stride: MIN_TRANSFER_STRIDE,
+            counters: Counters::default(),
+        }
+    }
+    /// Creates an empty map which will use `hash_builder` to hash keys, with an initial table
+    /// sized to accommodate `capacity` elements without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        let map = Self::with_hasher(hash_builder);
+        if capacity > 0 {
+            let cap = if capacity >= (MAXIMUM_CAPACITY >> 1) {
+                MAXIMUM_CAPACITY
+            } else {
+                table_size_for(capacity + (capacity >> 1) + 1)
+            };
+            map.size_ctl.store(cap as isize, Ordering::Relaxed);
+        }
+        map
+    }
+    /// Like `with_concurrency_level`, but uses `hash_builder` to hash keys.
+    pub fn with_concurrency_level_and_hasher(
+        initial_capacity: usize,
+        load_factor: f32,
+        concurrency_level: usize,
+        hash_builder: S,
+    ) -> Self {
+        assert!(load_factor > 0.0, "load factor must be positive");
+        assert!(concurrency_level > 0, "concurrency level must be positive");
+        // use at least as many bins as estimated threads
+        let initial_capacity = initial_capacity.max(concurrency_level);
+        let size = 1.0 + initial_capacity as f64 / load_factor as f64;
+        let cap = if size >= MAXIMUM_CAPACITY as f64 {
+            MAXIMUM_CAPACITY
+        } else {
+            table_size_for(size as usize)
+        };
+        let map = Self::with_hasher(hash_builder);
+        map.size_ctl.store(cap as isize, Ordering::Relaxed);
+        map
+    }
+    /// Resizes the table so that at least `additional` more elements can be inserted without
+    /// triggering a resize. Does nothing if the table is already large enough.
+    ///
+    /// Only an unallocated table is created at the target size directly; an allocated table is
+    /// still grown by doubling, one full transfer per step, so this saves no work over inserting
+    /// the elements and only moves the resizes ahead of the inserts.
+    pub fn reserve(&self, additional: usize) {
+        let size = self.size().saturating_add(additional);
+        let guard = self.collector.pin();
+        // try_presize grows an existing table by one step at a time, so repeat until the resize
+        // threshold covers the requested size
+        loop {
+            let sc = self.size_ctl.load(Ordering::Acquire);
+            let tab = self.table.load(Ordering::Acquire);
+            if sc < 0 {
+                let nt = self.next_table.load(Ordering::Acquire);
+                match unsafe { tab.as_ref() } {
+                    Some(tab) if !nt.is_null() => unsafe { self.help_transfer(tab, nt, &guard) },
+                    // initializing, or the resize is being committed
+                    _ => spin_loop(),
+                }
+                continue;
+            }
+            if let Some(tab) = unsafe { tab.as_ref() } {
+                if sc as usize >= size || tab.len() >= MAXIMUM_CAPACITY {
+                    return;
+                }
+            }
+            unsafe { self.try_presize(size, &guard) }
+        }
+    }
+    /// Shrinks the table as far as the current number of elements allows, never below the
+    /// default capacity. The table is halved one step at a time by a transfer that merges
+    /// pairs of bins, which concurrent operations help with like with any other resize. A
+    /// resize already in progress is helped before shrinking.
+    pub fn shrink_to_fit(&self) {
+        let guard = self.collector.pin();
+        loop {
+            let sc = self.size_ctl.load(Ordering::Acquire);
+            let tab_ptr = self.table.load(Ordering::Acquire);
+            if sc < 0 {
+                let nt = self.next_table.load(Ordering::Acquire);
+                match unsafe { tab_ptr.as_ref() } {
+                    Some(tab) if !nt.is_null() => unsafe { self.help_transfer(tab, nt, &guard) },
+                    // initializing, or the resize is being committed
+                    _ => spin_loop(),
+                }
+                continue;
+            }
+            let tab = match unsafe { tab_ptr.as_ref() } {
+                Some(tab) => tab,
+                None => return,
+            };
+            let n = tab.len();
+            let c = self.size();
+            let target = if c >= MAXIMUM_CAPACITY >> 1 {
+                MAXIMUM_CAPACITY
+            } else {
+                table_size_for(c + (c >> 1) + 1).max(DEFAULT_CAPACITY)
+            };
+            if n <= target {
+                return;
+            }
+            let rs = resize_stamp(n as isize);
+            if self
+                .size_ctl
+                .compare_exchange(
+                    sc,
+                    (rs << RESIZE_STAMP_SHIFT) + 2,
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+            {
+                continue;
+            }
+            if self.table.load(Ordering::Acquire) != tab_ptr {
+                // resized in between
+                self.size_ctl.store(sc, Ordering::Release);
+                continue;
+            }
+            match Self::new_tab(n >> 1) {
+                Ok(nt) => unsafe {
+                    self.next_table.store(nt, Ordering::Release);
+                    self.transfer_index
+                        .store((n >> 1) as isize, Ordering::Release);
+                    self.transfer(tab, Some(nt), &guard);
+                },
+                Err(e) => {
+                    self.size_ctl.store(sc, Ordering::Release);
+                    panic::resume_unwind(e);
+                }
+            }
         }
     }
+    /// Returns the entry for `key`, locking the bin it maps to until the entry is dropped.
+    /// An empty bin is claimed with a reservation, like in `compute_if_absent`.
+    /// Writing to the map from the same thread while holding an entry may deadlock.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        let hash = self.spread(&key);
+        let guard = self.collector.pin();
+        unsafe {
+            let bin = match self.lock_bin(hash, true, &guard) {
+                Some(bin) => bin,
+                None => unreachable!("lock_bin reserves empty bins"),
+            };
+            let (slot, tail) = match &mut *bin.node {
+                NodeEnums::ReservationNode(_) => (None, Tail::Reserved),
+                NodeEnums::Node(head) => {
+                    let mut bin_count = 1;
+                    let mut pred = ptr::null_mut::<Node<K, V>>();
+                    let mut e = head as *const Node<K, V> as *mut Node<K, V>;
+                    loop {
+                        if (*e).hash == hash && *(*e).key == key {
+                            break (Some(Slot::Linked { pred, e }), Tail::Reserved);
+                        }
+                        let next = (*e).next.load(Ordering::Acquire);
+                        if next.is_null() {
+                            break (None, Tail::Linked { last: e, bin_count });
+                        }
+                        pred = e;
+                        e = next;
+                        bin_count += 1;
+                    }
+                }
+                NodeEnums::TreeBin(t) => (t.find_key_node(hash, &key).map(Slot::Tree), Tail::Tree),
+                NodeEnums::ForwardingNode(_) => unreachable!("lock_bin never returns a moved bin"),
+            };
+            match slot {
+                Some(slot) => Entry::Occupied(OccupiedEntry {
+                    bin,
+                    map: self,
+                    slot,
+                    guard,
+                }),
+                None => Entry::Vacant(VacantEntry {
+                    bin,
+                    map: self,
+                    hash,
+                    key: Some(key),
+                    tail,
+                    guard,
+                }),
+            }
+        }
+    }
+    /// If the specified key is not already associated with a value, associates it with the
+    /// given value.
+    /// Returns the present value, or `None` if the value was inserted.
+    pub fn put_if_absent(&self, key: K, value: V) -> Option<Value<'_, V>> {
+        let guard = self.collector.pin();
+        match unsafe { self.put_val(key, value, true, &guard) } {
+            Ok(_) => None,
+            Err((_, _, present)) => Some(Value::new(guard, present)),
+        }
+    }
+    /// Inserts the key and value only if the key is absent.
+    /// Returns the rejected key and value together with the present value otherwise.
+    pub fn try_insert(&self, key: K, value: V) -> Result<(), (K, V, Value<'_, V>)> {
+        let guard = self.collector.pin();
+        match unsafe { self.put_val(key, value, true, &guard) } {
+            Ok(_) => Ok(()),
+            Err((key, value, present)) => Err((key, value, Value::new(guard, present))),
+        }
+    }
+    /// Returns a clone of the value to which the specified key is mapped, or `None` if this
+    /// map contains no mapping for the key. Unlike a `Value`, the clone does not keep the
+    /// collector pinned, so it can be held for as long as needed.
+    pub fn get_cloned<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: Clone,
+    {
+        let _guard = self.collector.pin();
+        unsafe { self.find(key, None).map(|v| (*v).clone()) }
+    }
+    /// Sets the executor running the batches of bulk operations such as `for_each`, instead
+    /// of the default `ScopedThreads`.
+    pub fn set_executor<E>(&mut self, executor: E)
+    where
+        E: Executor + 'static,
+    {
+        self.executor = Box::new(executor);
+    }
+    pub(crate) fn executor(&self) -> &dyn Executor {
+        &*self.executor
+    }
+    /// Computes initial batch value for bulk tasks. The returned value is approximately
+    /// exp2 of the number of times (minus one) to split task by two before executing leaf
+    /// action. This value is faster to compute and more convenient to use as a guide to
+    /// splitting than is the depth, since it is used while dividing by two anyway.
+    fn batch_for(&self, b: usize) -> usize {
+        batch_for_size(self.sum_count().max(0) as usize, b)
+    }
+    /// Divides the current table into ranges of bins, halving the remaining range of a batch
+    /// as long as its batch value allows like the JDK's `BulkTask`, and returns a traverser
+    /// for each range.
+    pub(crate) fn bulk_traversers<'g>(
+        &'g self,
+        parallelism_threshold: usize,
+        guard: &'g Guard<'_>,
+    ) -> Vec<Traverser<'g, K, V>> {
+        fn split(i: usize, mut f: usize, mut batch: usize, ranges: &mut Vec<(usize, usize)>) {
+            while batch > 0 {
+                let h = (i + f) >> 1;
+                if h <= i {
+                    break;
+                }
+                batch >>= 1;
+                split(h, f, batch, ranges);
+                f = h;
+            }
+            ranges.push((i, f));
+        }
+        self.check_guard(guard);
+        let tab = unsafe { self.table.load(Ordering::Acquire).as_ref() }.map(|t| &**t);
+        let n = tab.map_or(0, |t| t.len());
+        let mut ranges = Vec::new();
+        split(0, n, self.batch_for(parallelism_threshold), &mut ranges);
+        ranges
+            .into_iter()
+            .map(|(i, f)| Traverser::new(tab, n, i, f))
+            .collect()
+    }
+    /// Pins the collector of this map. Everything read from the map through the returned guard
+    /// stays allocated until it is dropped, so keep it short lived.
+    pub fn guard(&self) -> Guard<'_> {
+        self.collector.pin()
+    }
+    /// Returns an iterator over the entries of this map, in arbitrary order.
+    /// The iterator is weakly consistent, and the references it yields live as long as `guard`,
+    /// which must come from `guard` on this map.
+    pub fn iter<'g>(&'g self, guard: &'g Guard<'_>) -> Iter<'g, K, V> {
+        Iter {
+            it: self.traverser(guard),
+        }
+    }
+    /// Returns an iterator over the keys of this map, in arbitrary order.
+    /// See `iter` for its guarantees.
+    pub fn keys<'g>(&'g self, guard: &'g Guard<'_>) -> Keys<'g, K, V> {
+        Keys {
+            it: self.traverser(guard),
+        }
+    }
+    /// Returns an iterator over the values of this map, in arbitrary order.
+    /// See `iter` for its guarantees.
+    pub fn values<'g>(&'g self, guard: &'g Guard<'_>) -> Values<'g, K, V> {
+        Values {
+            it: self.traverser(guard),
+        }
+    }
+    /// Returns a traverser over the whole current table.
+    pub(crate) fn traverser<'g>(&'g self, guard: &'g Guard<'_>) -> Traverser<'g, K, V> {
+        self.check_guard(guard);
+        let tab = unsafe { self.table.load(Ordering::Acquire).as_ref() }.map(|t| &**t);
+        let n = tab.map_or(0, |t| t.len());
+        Traverser::new(tab, n, 0, n)
+    }
+    /// References handed out under `guard` are only protected by this map's collector.
+    #[inline]
+    pub(crate) fn check_guard(&self, guard: &Guard<'_>) {
+        assert!(
+            ptr::eq(guard.collector(), &self.collector),
+            "guard is not pinned to the collector of this map"
+        );
+    }
 }
 
-impl<K, V> Map<K, V> for ConcurrentHashMap<K, V>
+impl<K, V> ConcurrentHashMap<K, V, RandomState>
 where
     K: Hash + Eq + Send + 'static,
     V: Send + 'static,
 {
+    pub fn new() -> ConcurrentHashMap<K, V> {
+        Self::with_hasher(RandomState::new())
+    }
+    /// Creates an empty map with an initial table sized to accommodate `capacity` elements
+    /// without resizing.
+    pub fn with_capacity(capacity: usize) -> ConcurrentHashMap<K, V> {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+    /// Creates an empty map with an initial table size based on the given number of elements
+    /// (`initial_capacity`), table density (`load_factor`), and number of concurrently updating
+    /// threads (`concurrency_level`).
+    /// Panics if `load_factor` is not positive or `concurrency_level` is zero.
+    pub fn with_concurrency_level(
+        initial_capacity: usize,
+        load_factor: f32,
+        concurrency_level: usize,
+    ) -> ConcurrentHashMap<K, V> {
+        Self::with_concurrency_level_and_hasher(
+            initial_capacity,
+            load_factor,
+            concurrency_level,
+            RandomState::new(),
+        )
+    }
+}
+
+impl<K, V> ConcurrentHashMap<K, V, RandomState>
+where
+    K: Ord + Hash + Send + 'static,
+    V: Send + 'static,
+{
+    /// Creates an empty map whose tree bins order keys with equal hashes by `Ord`.
+    /// See `with_ordered_bins_and_hasher`.
+    pub fn with_ordered_bins() -> ConcurrentHashMap<K, V> {
+        Self::with_ordered_bins_and_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Ord + Hash + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Creates an empty map which will use `hash_builder` to hash keys, and whose tree bins
+    /// order keys with equal hashes by `Ord`. Inserts, lookups, removals and the compute family
+    /// stay logarithmic in a bin even if all of its keys collide, at the cost of a comparison
+    /// per tree level. Lookups and removals by key only use the order when made through
+    /// `ordered_view`, which requires an ordered borrowed form of the key, otherwise they
+    /// scan the keys sharing their hash.
+    pub fn with_ordered_bins_and_hasher(hash_builder: S) -> Self {
+        let mut map = Self::with_hasher(hash_builder);
+        map.key_cmp = Some(K::cmp);
+        map
+    }
+}
+
+impl<K, V, S> Default for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> ConcurrentMap<K, V> for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    type Ref<'a>
+        = Value<'a, V>
+    where
+        Self: 'a;
+
     fn size(&self) -> usize {
         let n = self.sum_count();
         if n < 0 {
@@ -178,37 +675,274 @@ where
         }
     }
 
-    fn get(&self, key: &K) -> Option<Value<V>> {
-        let h = self.spread(key);
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(key).is_some()
+    }
+    fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let guard = self.collector.pin();
+        let found = self.iter(&guard).any(|(_, v)| v == value);
+        found
+    }
+
+    fn get<Q>(&self, key: &Q) -> Option<Value<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let guard = self.collector.pin();
+        unsafe { self.find(key, None) }.map(|v| Value::new(guard, v))
+    }
+    fn insert(&self, key: K, value: V) -> Option<Value<'_, V>> {
+        let guard = self.collector.pin();
+        match unsafe { self.put_val(key, value, false, &guard) } {
+            Ok(old) => old.map(|old| Value::new_drop(guard, old)),
+            Err(_) => unreachable!("only rejected if absent was requested"),
+        }
+    }
+    fn remove<Q>(&self, key: &Q) -> Option<Value<'_, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let guard = self.collector.pin();
+        unsafe { self.replace_node(key, None, None, |_| true, &guard) }
+            .map(|old| Value::new_drop(guard, old))
+    }
+    fn put_if_absent(&self, key: K, value: V) -> Option<Value<'_, V>> {
+        ConcurrentHashMap::put_if_absent(self, key, value)
+    }
+    fn remove_entry<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        ConcurrentHashMap::remove_entry(self, key, value)
+    }
+    fn replace<Q>(&self, key: &Q, old_value: &V, new_value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        ConcurrentHashMap::replace(self, key, old_value, new_value)
+    }
+    fn compute_if_absent<F>(&self, key: K, f: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K) -> Option<V>,
+    {
+        ConcurrentHashMap::compute_if_absent(self, key, f)
+    }
+    fn compute_if_present<F>(&self, key: &K, f: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K, &V) -> Option<V>,
+    {
+        ConcurrentHashMap::compute_if_present(self, key, f)
+    }
+    fn compute<F>(&self, key: K, f: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K, Option<&V>) -> Option<V>,
+    {
+        ConcurrentHashMap::compute(self, key, f)
+    }
+    fn merge<F>(&self, key: K, value: V, f: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&V, V) -> Option<V>,
+    {
+        ConcurrentHashMap::merge(self, key, value, f)
+    }
+    fn clear(&self) {
         let guard_ = self.collector.pin();
-        let tab = self.table.load(Ordering::Acquire);
-        if tab.is_null() {
-            return None;
+        let guard = &guard_;
+        // negative number of deletions
+        let mut delta = 0;
+        let mut i = 0;
+        let mut tab = unsafe { self.table.load(Ordering::Acquire).as_ref() };
+        while let Some(t) = tab {
+            if i >= t.len() {
+                break;
+            }
+            let f = &t[i];
+            let f_node_ptr = f.node.load(Ordering::Acquire);
+            unsafe {
+                match f_node_ptr.as_ref() {
+                    None => i += 1,
+                    Some(NodeEnums::ForwardingNode(fwd)) => {
+                        self.help_transfer(t, fwd.next_table, guard);
+                        tab = fwd.next_table.as_ref();
+                        i = 0;
+                    }
+                    Some(_) => {
+                        let mutex_guard = f.lock();
+                        if f.node.load(Ordering::Acquire) == f_node_ptr {
+                            f.node.store(ptr::null_mut(), Ordering::Release);
+                            delta -= NodeEnums::reclaim_bin(f_node_ptr, guard);
+                            i += 1;
+                        }
+                        drop(mutex_guard);
+                    }
+                }
+            }
         }
-        unsafe {
-            let tab = &*tab;
-            let n = tab.len();
+        if delta != 0 {
+            unsafe { self.add_count(delta, -1, guard) };
+        }
+    }
+    fn for_each_entry<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        let guard = self.collector.pin();
+        for (k, v) in self.iter(&guard) {
+            f(k, v);
+        }
+    }
+}
 
-            let eb = (*tab.as_ptr().add((n - 1) & h)).node.load(Ordering::Relaxed);
-            if eb.is_null() {
-                return None;
+impl<K, V, S> Drop for ConcurrentHashMap<K, V, S> {
+    fn drop(&mut self) {
+        unsafe {
+            for table in [self.next_table.get_mut(), self.table.get_mut()] {
+                let tab = *table;
+                if tab.is_null() {
+                    continue;
+                }
+                for f in (*tab).iter() {
+                    let p = f.node.swap(ptr::null_mut(), Ordering::Relaxed);
+                    if let Some(NodeEnums::ForwardingNode(_)) | None = p.as_ref() {
+                        // the nodes live on in the next table
+                        f.node.store(p, Ordering::Relaxed);
+                    } else {
+                        NodeEnums::reclaim_bin(p, &Unprotected);
+                    }
+                }
+                drop(Box::from_raw(tab));
+            }
+            let cc = *self.counter_cells.get_mut();
+            if !cc.is_null() {
+                drop(Box::from_raw(cc));
             }
-            match &*eb {
-                NodeEnums::Node(e) => e.find(h,key),
-                NodeEnums::ForwardingNode(e) => e.find(h, key),
-                NodeEnums::TreeBin(e) => e.find(h, key),
-            }.map(|v| Value::new(guard_, v))
         }
     }
-    fn insert(&self, key: K, value: V) -> Option<Value<V>> {
-        unsafe { self.put_val(key, value, false) }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// If the specified key is not already associated with a value, attempts to compute its
+    /// value using the given mapping function and enters it into this map unless `None`.
+    /// The entire method invocation is performed atomically, so the function is applied at
+    /// most once per key. Some attempted update operations on this map by other threads may be
+    /// blocked while computation is in progress, so the computation should be short and simple,
+    /// and must not attempt to update any other mappings of this map. Updating one that shares
+    /// the bin of the key panics with "Recursive update".
+    /// Returns the current (existing or computed) value associated with the specified key, or
+    /// `None` if the computed value is `None`.
+    pub fn compute_if_absent<F>(&self, key: K, mapping_function: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K) -> Option<V>,
+    {
+        let mut key = ManuallyDrop::new(key);
+        unsafe { self.compute_val(&mut *key, Remap::Absent, |k, _| mapping_function(k)) }
+    }
+    /// If the value for the specified key is present, attempts to compute a new mapping given
+    /// the key and its current mapped value. The mapping is removed if the function returns
+    /// `None`. The entire method invocation is performed atomically, with the same restrictions
+    /// on the function as in `compute_if_absent`.
+    /// Returns the new value associated with the specified key, or `None` if none.
+    pub fn compute_if_present<F>(&self, key: &K, remapping_function: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K, &V) -> Option<V>,
+    {
+        unsafe {
+            self.compute_val(key as *const K as *mut K, Remap::Present, |k, v| {
+                v.and_then(|v| remapping_function(k, v))
+            })
+        }
+    }
+    /// Attempts to compute a mapping for the specified key and its current mapped value (or
+    /// `None` if there is no current mapping). The mapping is removed, or stays absent, if the
+    /// function returns `None`. The entire method invocation is performed atomically, with the
+    /// same restrictions on the function as in `compute_if_absent`.
+    /// Returns the new value associated with the specified key, or `None` if none.
+    pub fn compute<F>(&self, key: K, remapping_function: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K, Option<&V>) -> Option<V>,
+    {
+        let mut key = ManuallyDrop::new(key);
+        unsafe { self.compute_val(&mut *key, Remap::Always, remapping_function) }
+    }
+    /// If the specified key is not already associated with a value, associates it with the
+    /// given value. Otherwise, replaces the value with the results of the given remapping
+    /// function, or removes it if the result is `None`. The entire method invocation is
+    /// performed atomically, with the same restrictions on the function as in
+    /// `compute_if_absent`.
+    /// Returns the new value associated with the specified key, or `None` if none.
+    pub fn merge<F>(&self, key: K, value: V, remapping_function: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&V, V) -> Option<V>,
+    {
+        let mut key = ManuallyDrop::new(key);
+        unsafe {
+            self.compute_val(&mut *key, Remap::Always, |_, old| match old {
+                None => Some(value),
+                Some(old) => remapping_function(old, value),
+            })
+        }
+    }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: PartialEq + Send + 'static,
+    S: BuildHasher,
+{
+    /// Removes the entry for a key only if it is currently mapped to the given value.
+    /// Returns true if the value was removed.
+    pub fn remove_entry<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let guard = self.collector.pin();
+        unsafe {
+            self.replace_node(key, None, None, |v| v == value, &guard)
+                .map(|old| guard.defer_destroy(old))
+                .is_some()
+        }
+    }
+    /// Replaces the entry for a key only if it is currently mapped to the given value.
+    /// Returns true if the value was replaced.
+    pub fn replace<Q>(&self, key: &Q, old_value: &V, new_value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let guard = self.collector.pin();
+        unsafe {
+            self.replace_node(key, None, Some(new_value), |v| v == old_value, &guard)
+                .map(|old| guard.defer_destroy(old))
+                .is_some()
+        }
     }
 }
 
-impl<K, V> ConcurrentHashMap<K, V>
+impl<K, V, S> ConcurrentHashMap<K, V, S>
 where
     K: Hash + Eq + Send + 'static,
     V: Send + 'static,
+    S: BuildHasher,
 {
     fn init_table(&self) {
         loop {
@@ -255,8 +989,10 @@ where
     /// Params:
     ///  x    – the count to add
     /// check – if <0, don't check resize, if <= 1 only check if uncontended
-    unsafe fn add_count(&self, x: isize, check: isize, guard: &Guard) {
-        let mut s = 0;
+    pub(crate) unsafe fn add_count(&self, x: isize, check: isize, guard: &Guard) {
+        #[cfg(test)]
+        COUNT_UPDATES.with(|c| c.set(c.get() + 1));
+        let mut s;
         let cc = self.counter_cells.load(Ordering::Acquire);
         let h = self.hash_builder.hash_one(thread::current().id()) as usize;
         if cc.is_null() {
@@ -271,7 +1007,7 @@ where
                 return;
             }
         } else {
-            let cc = unsafe { &*cc };
+            let cc = &*cc;
             let m = cc.len() - 1;
             let a = &cc[h & m];
             a.fetch_add(x, Ordering::Release);
@@ -294,10 +1030,7 @@ where
                     }
                     let rs = resize_stamp(n as isize);
                     if sc < 0 {
-                        if (sc >> RESIZE_STAMP_SHIFT) != rs
-                            || sc == rs + 1
-                            || sc == rs + MAX_RESIZERS
-                        {
+                        if !can_help_resize(sc, n) {
                             break;
                         }
                         let nt = self.next_table.load(Ordering::Acquire);
@@ -376,7 +1109,7 @@ where
             self.base_count.fetch_add(x, Ordering::Release);
         }
     }
-    fn sum_count(&self) -> isize {
+    pub(crate) fn sum_count(&self) -> isize {
         unsafe {
             let cc = self.counter_cells.load(Ordering::Acquire);
             let mut sum = self.base_count.load(Ordering::Acquire);
@@ -389,13 +1122,46 @@ where
         }
     }
 
-    unsafe fn put_val(&self, key: K, value: V, only_if_absent: bool) -> Option<Value<V>> {
+    /// Returns the value mapped to `key`, which stays valid while the caller is pinned.
+    /// `ord` orders `key` against the keys of ordered tree bins, see `KeyOrd`.
+    pub(crate) unsafe fn find<Q>(&self, key: &Q, ord: KeyOrd<'_, K>) -> Option<*mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let h = self.spread(key);
+        let tab = self.table.load(Ordering::Acquire);
+        if tab.is_null() {
+            return None;
+        }
+        let tab = &*tab;
+        let n = tab.len();
+
+        let eb = (*tab.as_ptr().add((n - 1) & h))
+            .node
+            .load(Ordering::Acquire);
+        if eb.is_null() {
+            return None;
+        }
+        match &*eb {
+            NodeEnums::Node(e) => e.find(h, key),
+            NodeEnums::ForwardingNode(e) => e.find(h, key, ord),
+            NodeEnums::TreeBin(e) => e.find(h, key, ord),
+            NodeEnums::ReservationNode(e) => e.find(h, key),
+        }
+    }
+    /// Implementation for insert, put_if_absent and try_insert.
+    /// Returns the replaced value, which the caller must retire through `guard`, or with
+    /// `only_if_absent`, the rejected key and value together with the present value.
+    pub(crate) unsafe fn put_val(
+        &self,
+        mut key: K,
+        value: V,
+        only_if_absent: bool,
+        guard: &Guard,
+    ) -> Result<Option<*mut V>, (K, V, *mut V)> {
         let hash = self.spread(&key);
-        let key = Box::into_raw(Box::new(key)) as *const _;
         let value = Box::into_raw(Box::new(value));
-        let mut node_option = None;
-        let guard_ = self.collector.pin();
-        let guard = &guard_;
         let mut bin_count = 0;
         let old = 'a: loop {
             let tab = self.table.load(Ordering::Acquire);
@@ -412,42 +1178,42 @@ where
             let f_node_atomic = &f.node;
             let f_node_ptr = f_node_atomic.load(Ordering::Acquire);
             if f_node_ptr.is_null() {
-                let node = node_option.unwrap_or_else(|| {
-                    Box::into_raw(Box::new(NodeEnums::Node(Node::new(hash, key, value))))
-                });
+                let node = NodeEnums::Node(Node::new(hash, key, value)).into_box();
                 match f_node_atomic.compare_exchange(
                     f_node_ptr,
                     node,
                     Ordering::AcqRel,
                     Ordering::Relaxed,
                 ) {
-                    Ok(_) => {
-                        break None;
-                    }
-                    Err(e) => {
-                        node_option = Some(e);
+                    Ok(_) => break None,
+                    Err(_) => {
+                        // lost the race for the empty bin, take the key back for the retry
+                        key = match *Box::from_raw(node) {
+                            NodeEnums::Node(node) => node.into_key(),
+                            _ => unreachable!(),
+                        };
                         continue;
                     }
                 }
             }
-            let f_node = &mut *f_node_ptr;
+            let f_node = &*f_node_ptr;
             if let NodeEnums::ForwardingNode(f_move) = f_node {
                 self.help_transfer(tab, f_move.next_table, guard);
             } else {
-                let mutex_guard = f.lock.lock();
+                let mutex_guard = f.lock();
                 if f_node_atomic.load(Ordering::Acquire) == f_node_ptr {
                     match f_node {
                         NodeEnums::Node(link_node) => {
                             bin_count = 1;
                             let mut e = link_node;
                             loop {
-                                if e.hash == hash && e.key == key {
-                                    let old = e.val;
+                                if e.hash == hash && *e.key == key {
+                                    let old = e.val.load(Ordering::Acquire);
                                     if !only_if_absent {
-                                        e.val = value;
+                                        e.val.store(value, Ordering::Release);
                                         //由返回的引用释放value
                                     }
-                                    break 'a Some(old);
+                                    break 'a Some((old, key));
                                 }
                                 let next = e.next.load(Ordering::Acquire);
                                 if next.is_null() {
@@ -456,24 +1222,25 @@ where
                                         Ordering::Release,
                                     );
                                     drop(mutex_guard);
-                                    if bin_count >= TREEIFY_THRESHOLD {
+                                    if bin_count >= self.treeify_threshold {
                                         self.treeify_bin(tab, i, guard);
                                     }
                                     break 'a None;
                                 }
-                                e = &mut *next;
+                                e = &*next;
                                 bin_count += 1;
                             }
                         }
-                        NodeEnums::TreeBin(f) => {
+                        NodeEnums::TreeBin(_) => {
                             bin_count = 2;
-                            if let Some(p) = f.put_tree_val(hash, key, value, guard) {
-                                let old = p.val;
+                            let t = NodeEnums::tree_bin_mut(f_node_ptr);
+                            if let Some((p, key)) = t.put_tree_val(hash, key, value, guard) {
+                                let old = p.val.load(Ordering::Acquire);
                                 if !only_if_absent {
-                                    p.val = value;
+                                    p.val.store(value, Ordering::Release);
                                 }
                                 //由返回的引用释放value
-                                break 'a Some(old);
+                                break 'a Some((old, key));
                             }
                             break 'a None;
                         }
@@ -486,61 +1253,412 @@ where
         match old {
             None => {
                 self.add_count(1, bin_count as isize, guard);
-                None
+                Ok(None)
+            }
+            // the map keeps its own key, ours was never published
+            Some((v, key)) => {
+                if only_if_absent {
+                    let value = *Box::from_raw(value);
+                    Err((key, value, v))
+                } else {
+                    Ok(Some(v))
+                }
+            }
+        }
+    }
+    /// Implementation for the public remove/replace methods: Replaces node value with `value`,
+    /// conditional upon a match of `cv`. If `value` is `None`, removes the node.
+    /// Removed nodes and keys are retired through `guard`, the old value is handed back to the
+    /// caller, who must retire it. `ord` orders `key` against the keys of ordered tree bins.
+    pub(crate) unsafe fn replace_node<Q, F>(
+        &self,
+        key: &Q,
+        ord: KeyOrd<'_, K>,
+        mut value: Option<V>,
+        cv: F,
+        guard: &Guard,
+    ) -> Option<*mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: Fn(&V) -> bool,
+    {
+        let hash = self.spread(key);
+        let remove = value.is_none();
+        loop {
+            let tab = self.table.load(Ordering::Acquire).as_ref()?;
+            let n = tab.len();
+            let i = (n - 1) & hash;
+            let f = &tab[i];
+            let f_node_ptr = f.node.load(Ordering::Acquire);
+            let f_node = f_node_ptr.as_ref()?;
+            if let NodeEnums::ForwardingNode(f_move) = f_node {
+                self.help_transfer(tab, f_move.next_table, guard);
+                continue;
+            }
+            let mut old = None;
+            let mut validated = false;
+            let mutex_guard = f.lock();
+            if f.node.load(Ordering::Acquire) == f_node_ptr {
+                match f_node {
+                    NodeEnums::Node(head) => {
+                        validated = true;
+                        let mut pred = ptr::null_mut::<Node<K, V>>();
+                        let mut e = head as *const Node<K, V> as *mut Node<K, V>;
+                        loop {
+                            if (*e).hash == hash && (*(*e).key).borrow() == key {
+                                let ev = (*e).val.load(Ordering::Acquire);
+                                if cv(&*ev) {
+                                    old = Some(ev);
+                                    if let Some(value) = value.take() {
+                                        let value = Box::into_raw(Box::new(value));
+                                        (*e).val.store(value, Ordering::Release);
+                                    } else {
+                                        Self::unlink_node(f, f_node_ptr, pred, e, guard);
+                                    }
+                                }
+                                break;
+                            }
+                            pred = e;
+                            e = (*e).next.load(Ordering::Acquire);
+                            if e.is_null() {
+                                break;
+                            }
+                        }
+                    }
+                    NodeEnums::TreeBin(_) => {
+                        validated = true;
+                        let t = NodeEnums::tree_bin_mut(f_node_ptr);
+                        if let Some(p) = t.find_node(hash, key, ord) {
+                            let pv = (*(*p).node).val.load(Ordering::Acquire);
+                            if cv(&*pv) {
+                                old = Some(pv);
+                                if let Some(value) = value.take() {
+                                    let value = Box::into_raw(Box::new(value));
+                                    (*(*p).node).val.store(value, Ordering::Release);
+                                } else {
+                                    self.remove_tree_val(f, f_node_ptr, t, p, guard);
+                                }
+                            }
+                        }
+                    }
+                    NodeEnums::ForwardingNode(_) | NodeEnums::ReservationNode(_) => {}
+                }
+            }
+            drop(mutex_guard);
+            if validated {
+                let old = old?;
+                if remove {
+                    self.add_count(-1, -1, guard);
+                }
+                return Some(old);
+            }
+        }
+    }
+    /// Implementation for compute, compute_if_absent, compute_if_present and merge. Runs the
+    /// remapping function at most once, while holding the lock of the bin the key maps to.
+    /// Empty bins are claimed with a `ReservationNode` before the function is called.
+    /// `key` is owned by this call, which either moves it into the map or drops it, except for
+    /// `Remap::Present` where it is only borrowed because the key is never inserted, and must
+    /// not be written through.
+    /// Returns the value now mapped to the key, or the present value for `Remap::Absent`.
+    unsafe fn compute_val<F>(&self, key: *mut K, mode: Remap, f: F) -> Option<Value<'_, V>>
+    where
+        F: FnOnce(&K, Option<&V>) -> Option<V>,
+    {
+        let hash = self.spread(&*key);
+        let mut f = Some(f);
+        // the bin is recorded as locked by the function for `BaseNode::lock`
+        let mut remap = |bin: &BaseNode<K, V>, old: Option<&V>| {
+            let f = f.take().unwrap();
+            let bin = bin as *const BaseNode<K, V> as usize;
+            COMPUTING.with(|c| c.borrow_mut().push(bin));
+            let val = panic::catch_unwind(AssertUnwindSafe(|| f(&*key, old)));
+            COMPUTING.with(|c| c.borrow_mut().pop());
+            val.map(|val| val.map(|val| Box::into_raw(Box::new(val))))
+        };
+        let guard_ = self.collector.pin();
+        let guard = &guard_;
+        let mut bin_count = 0;
+        let mut delta = 0;
+        let mut key_used = false;
+        let val = 'a: {
+            let bin = match self.lock_bin(hash, mode != Remap::Present, guard) {
+                Some(bin) => bin,
+                None => break 'a Ok(None),
+            };
+            let LockedBin {
+                tab,
+                index: i,
+                node: f_node_ptr,
+                lock: mutex_guard,
+            } = bin;
+            let f = &tab[i];
+            match &*f_node_ptr {
+                NodeEnums::ReservationNode(_) => {
+                    bin_count = 1;
+                    let val = remap(f, None);
+                    let node = match val {
+                        Ok(Some(val)) => {
+                            delta = 1;
+                            key_used = true;
+                            NodeEnums::Node(Node::new(hash, ptr::read(key), val)).into_box()
+                        }
+                        _ => ptr::null_mut(),
+                    };
+                    f.node.store(node, Ordering::Release);
+                    guard.defer_destroy(f_node_ptr);
+                    val
+                }
+                NodeEnums::Node(head) => {
+                    bin_count = 1;
+                    let mut pred = ptr::null_mut::<Node<K, V>>();
+                    let mut e = head as *const Node<K, V> as *mut Node<K, V>;
+                    loop {
+                        if (*e).hash == hash && *(*e).key == *key {
+                            let ev = (*e).val.load(Ordering::Acquire);
+                            if mode == Remap::Absent {
+                                break 'a Ok(Some(ev));
+                            }
+                            let val = remap(f, Some(&*ev));
+                            match val {
+                                Ok(Some(val)) => (*e).val.store(val, Ordering::Release),
+                                Ok(None) => {
+                                    delta = -1;
+                                    Self::unlink_node(f, f_node_ptr, pred, e, guard);
+                                }
+                                Err(_) => break 'a val,
+                            }
+                            guard.defer_destroy(ev);
+                            break 'a val;
+                        }
+                        pred = e;
+                        e = (*e).next.load(Ordering::Acquire);
+                        if e.is_null() {
+                            if mode == Remap::Present {
+                                break 'a Ok(None);
+                            }
+                            let val = remap(f, None);
+                            if let Ok(Some(val)) = val {
+                                delta = 1;
+                                key_used = true;
+                                let node = Node::new(hash, ptr::read(key), val).into_box();
+                                (*pred).next.store(node, Ordering::Release);
+                                drop(mutex_guard);
+                                if bin_count >= self.treeify_threshold {
+                                    self.treeify_bin(tab, i, guard);
+                                }
+                            }
+                            break 'a val;
+                        }
+                        bin_count += 1;
+                    }
+                }
+                NodeEnums::TreeBin(_) => {
+                    bin_count = 2;
+                    let t = NodeEnums::tree_bin_mut(f_node_ptr);
+                    if let Some(p) = t.find_key_node(hash, &*key) {
+                        let pv = (*(*p).node).val.load(Ordering::Acquire);
+                        if mode == Remap::Absent {
+                            break 'a Ok(Some(pv));
+                        }
+                        let val = remap(f, Some(&*pv));
+                        match val {
+                            Ok(Some(val)) => (*(*p).node).val.store(val, Ordering::Release),
+                            Ok(None) => {
+                                delta = -1;
+                                self.remove_tree_val(f, f_node_ptr, t, p, guard);
+                            }
+                            Err(_) => break 'a val,
+                        }
+                        guard.defer_destroy(pv);
+                        break 'a val;
+                    }
+                    if mode == Remap::Present {
+                        break 'a Ok(None);
+                    }
+                    let val = remap(f, None);
+                    if let Ok(Some(val)) = val {
+                        delta = 1;
+                        key_used = true;
+                        t.put_tree_val(hash, ptr::read(key), val, guard);
+                    }
+                    val
+                }
+                NodeEnums::ForwardingNode(_) => unreachable!("lock_bin never returns a moved bin"),
+            }
+        };
+        if mode != Remap::Present && !key_used {
+            ptr::drop_in_place(key);
+        }
+        let val = match val {
+            Ok(val) => val,
+            Err(e) => panic::resume_unwind(e),
+        };
+        if delta != 0 {
+            self.add_count(delta, bin_count as isize, guard);
+        }
+        val.map(|val| Value::new(guard_, val))
+    }
+    /// Locks the bin that `hash` maps to, helping with any resize in the way, and returns it
+    /// together with the head validated under the lock. An empty bin is claimed with a
+    /// `ReservationNode`, which the caller must replace, unless `reserve` is false, in which
+    /// case `None` is returned for it.
+    pub(crate) unsafe fn lock_bin(
+        &self,
+        hash: usize,
+        reserve: bool,
+        guard: &Guard,
+    ) -> Option<LockedBin<'_, K, V>> {
+        loop {
+            let tab = match self.table.load(Ordering::Acquire).as_ref() {
+                None => {
+                    self.init_table();
+                    continue;
+                }
+                Some(tab) => &**tab,
+            };
+            let i = (tab.len() - 1) & hash;
+            let f = &tab[i];
+            let f_node_ptr = f.node.load(Ordering::Acquire);
+            if f_node_ptr.is_null() {
+                if !reserve {
+                    return None;
+                }
+                let lock = f.lock();
+                let r = NodeEnums::ReservationNode(ReservationNode::new()).into_box();
+                if f.node
+                    .compare_exchange(ptr::null_mut(), r, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return Some(LockedBin {
+                        tab,
+                        index: i,
+                        node: r,
+                        lock,
+                    });
+                }
+                drop(lock);
+                drop(Box::from_raw(r));
+                continue;
+            }
+            if let NodeEnums::ForwardingNode(f_move) = &*f_node_ptr {
+                self.help_transfer(tab, f_move.next_table, guard);
+                continue;
+            }
+            let lock = f.lock();
+            if f.node.load(Ordering::Acquire) == f_node_ptr {
+                return Some(LockedBin {
+                    tab,
+                    index: i,
+                    node: f_node_ptr,
+                    lock,
+                });
+            }
+        }
+    }
+    /// Unlinks `e` from the linked bin `f` while holding its lock. `pred` is the node before
+    /// `e`, or null when `e` is the head stored inside `f_node_ptr`.
+    /// The node is retired with its key, the value is left to the caller.
+    pub(crate) unsafe fn unlink_node(
+        f: &BaseNode<K, V>,
+        f_node_ptr: *mut NodeEnums<K, V>,
+        pred: *mut Node<K, V>,
+        e: *mut Node<K, V>,
+        guard: &Guard,
+    ) {
+        let next = (*e).next.load(Ordering::Acquire);
+        if let Some(pred) = pred.as_ref() {
+            pred.next.store(next, Ordering::Release);
+            Node::retire(e, guard);
+        } else {
+            // the head lives inside the bin box, so the successor is copied into a fresh one
+            let hd = match next.as_ref() {
+                None => ptr::null_mut(),
+                Some(next) => {
+                    NodeEnums::Node(next.moved(next.next.load(Ordering::Acquire))).into_box()
+                }
+            };
+            f.node.store(hd, Ordering::Release);
+            NodeEnums::retire(f_node_ptr, guard);
+            if !next.is_null() {
+                guard.defer_destroy(next);
+            }
+        }
+    }
+    /// Removes `p` from the tree bin `t` stored in `f` while holding its lock, falling back to
+    /// a linked bin once the tree is too small.
+    /// The node is retired with its key, the value is left to the caller.
+    pub(crate) unsafe fn remove_tree_val(
+        &self,
+        f: &BaseNode<K, V>,
+        f_node_ptr: *mut NodeEnums<K, V>,
+        t: &mut TreeBin<K, V>,
+        p: *mut TreeNode<K, V>,
+        guard: &Guard,
+    ) {
+        if t.remove_tree_node(p, guard) {
+            let first = t.first.load(Ordering::Acquire);
+            let hd = match first.as_ref() {
+                None => ptr::null_mut(),
+                Some(first) => NodeEnums::Node(self.untreeify(first)).into_box(),
+            };
+            f.node.store(hd, Ordering::Release);
+            guard.defer_destroy(f_node_ptr);
+            if !first.is_null() {
+                guard.defer_destroy(first);
             }
-            Some(v) => Some(Value::new_drop(guard_, v)),
         }
     }
     /// Replaces all linked nodes in bin at given index unless table is
     /// too small, in which case resizes instead.
-    unsafe fn treeify_bin(&self, tab: &Box<[BaseNode<K, V>]>, index: usize, guard: &Guard) {
+    pub(crate) unsafe fn treeify_bin(&self, tab: &[BaseNode<K, V>], index: usize, guard: &Guard) {
         let n = tab.len();
-        if n < MIN_TREEIFY_CAPACITY {
+        if n < self.min_treeify_capacity {
             self.try_presize(n << 1, guard);
         } else {
             let tab_at = &tab[index];
             let b_shared = tab_at.node.load(Ordering::Acquire);
-            if let Some(b) = b_shared.as_ref() {
-                if let NodeEnums::Node(b) = b {
-                    let mutex_guard = tab_at.lock.lock();
-                    if b_shared == tab_at.node.load(Ordering::Acquire) {
-                        let mut e = b;
-                        let f = Node::new(e.hash, e.key, e.val).into_box();
-                        let hd = TreeNode::new(f).into_box();
-                        let mut tail = hd;
-                        let pd = e.next.load(Ordering::Relaxed);
-                        if !pd.is_null() {
+            if let Some(NodeEnums::Node(b)) = b_shared.as_ref() {
+                let mutex_guard = tab_at.lock();
+                if b_shared == tab_at.node.load(Ordering::Acquire) {
+                    let e = b;
+                    let f = e.moved(ptr::null_mut()).into_box();
+                    let hd = TreeNode::new(f).into_box();
+                    let mut tail = hd;
+                    let pd = e.next.load(Ordering::Relaxed);
+                    if !pd.is_null() {
+                        let p = TreeNode::new(pd).into_box();
+                        (*tail).right = p;
+                        (*pd).prev.store(f, Ordering::Relaxed);
+                        (*f).next.store(pd, Ordering::Relaxed);
+                        let mut e = pd;
+                        tail = p;
+                        // Reuse existing linked lists
+                        loop {
+                            let pd = (*e).next.load(Ordering::Relaxed);
+                            if pd.is_null() {
+                                break;
+                            }
                             let p = TreeNode::new(pd).into_box();
+                            // Use 'right' as' next '
                             (*tail).right = p;
-                            (*pd).prev.store(f, Ordering::Relaxed);
-                            (*f).next.store(pd,Ordering::Relaxed);
-                            let mut e = pd;
+                            // Do not maintain 'prev' when using linked lists
+                            (*pd).prev.store(e, Ordering::Relaxed);
                             tail = p;
-                            // Reuse existing linked lists
-                            loop {
-                                let pd = (*e).next.load(Ordering::Relaxed);
-                                if pd.is_null() {
-                                    break;
-                                }
-                                let p = TreeNode::new(pd).into_box();
-                                // Use 'right' as' next '
-                                (*tail).right = p;
-                                // Do not maintain 'prev' when using linked lists
-                                (*pd).prev.store(e, Ordering::Relaxed);
-                                tail = p;
-                                e = pd;
-                            }
-                        }
-                        let shared = tab_at.node.swap(
-                            NodeEnums::TreeBin(TreeBin::new(hd)).into_box(),
-                            Ordering::AcqRel,
-                        );
-                        if !shared.is_null() {
-                            guard.defer_destroy(shared);
+                            e = pd;
                         }
                     }
-                    drop(mutex_guard);
+                    let shared = tab_at.node.swap(
+                        NodeEnums::TreeBin(TreeBin::new(hd, self.key_cmp)).into_box(),
+                        Ordering::AcqRel,
+                    );
+                    if !shared.is_null() {
+                        guard.defer_destroy(shared);
+                    }
+                    self.counters.treeified();
                 }
+                drop(mutex_guard);
             }
         }
     }
@@ -554,14 +1672,17 @@ where
     /// to reduce systematic lossage, as well as to incorporate impact of the highest bits that would
     /// otherwise never be used in index calculations because of table bounds.
     #[inline]
-    fn spread(&self, key: &K) -> usize {
+    pub(crate) fn spread<Q>(&self, key: &Q) -> usize
+    where
+        Q: ?Sized + Hash,
+    {
         let hash = self.hash_builder.hash_one(key);
         HASH_BITS & (hash ^ (hash >> 32)) as usize
     }
     /// Tries to presize table to accommodate the given number of elements.
     /// Params:
     ///  size – number of elements (doesn't need to be perfectly accurate)
-    unsafe fn try_presize(&self, size: usize, guard: &Guard) {
+    pub(crate) unsafe fn try_presize(&self, size: usize, guard: &Guard) {
         let c = if size >= (MAXIMUM_CAPACITY >> 1) {
             MAXIMUM_CAPACITY
         } else {
@@ -580,19 +1701,18 @@ where
                 if size_ctl
                     .compare_exchange(sc, -1, Ordering::AcqRel, Ordering::Relaxed)
                     .is_ok()
+                    && table.load(Ordering::Acquire) == tab
                 {
-                    if table.load(Ordering::Acquire) == tab {
-                        match Self::new_tab(n) {
-                            Ok(tab) => {
-                                table.store(tab, Ordering::Release);
-                                sc = (n - (n >> 2)) as isize;
-                                size_ctl.store(sc, Ordering::Release);
-                                break;
-                            }
-                            Err(e) => {
-                                size_ctl.store(sc, Ordering::Release);
-                                panic::resume_unwind(e);
-                            }
+                    match Self::new_tab(n) {
+                        Ok(tab) => {
+                            table.store(tab, Ordering::Release);
+                            sc = (n - (n >> 2)) as isize;
+                            size_ctl.store(sc, Ordering::Release);
+                            break;
+                        }
+                        Err(e) => {
+                            size_ctl.store(sc, Ordering::Release);
+                            panic::resume_unwind(e);
                         }
                     }
                 }
@@ -601,26 +1721,23 @@ where
             } else {
                 let tab = &*tab;
                 let n = tab.len();
-                if n > MAXIMUM_CAPACITY {
+                if n >= MAXIMUM_CAPACITY {
                     break;
                 }
                 let rs = resize_stamp(n as isize);
                 if sc < 0 {
-                    if (sc >> RESIZE_STAMP_SHIFT) != rs || sc == rs + 1 || sc == rs + MAX_RESIZERS {
-                        let nt = self.next_table.load(Ordering::Acquire);
-                        if let Some(nt) = nt.as_ref() {
-                            if self.transfer_index.load(Ordering::Acquire) <= 0 {
-                                break;
-                            }
-                            if size_ctl
-                                .compare_exchange(sc, sc + 1, Ordering::AcqRel, Ordering::Relaxed)
-                                .is_ok()
-                            {
-                                self.transfer(tab, Some(nt), guard);
-                            }
-                        } else {
-                            break;
-                        }
+                    if !can_help_resize(sc, n) {
+                        break;
+                    }
+                    let nt = self.next_table.load(Ordering::Acquire);
+                    if nt.is_null() || self.transfer_index.load(Ordering::Acquire) <= 0 {
+                        break;
+                    }
+                    if size_ctl
+                        .compare_exchange(sc, sc + 1, Ordering::AcqRel, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        self.transfer(tab, Some(nt), guard);
                     }
                 } else if size_ctl
                     .compare_exchange(
@@ -638,18 +1755,23 @@ where
         }
     }
     /// Helps transfer if a resize is in progress.
-    unsafe fn help_transfer(
+    pub(crate) unsafe fn help_transfer(
         &self,
-        tab: &Box<[BaseNode<K, V>]>,
+        tab: &[BaseNode<K, V>],
         next_tab: *const Box<[BaseNode<K, V>]>,
         guard: &Guard,
     ) {
-        let rs = resize_stamp(tab.len() as isize);
-        loop {
+        // a stale `tab` may have the length of the table being resized now
+        while ptr::eq(self.next_table.load(Ordering::Acquire), next_tab)
+            && self
+                .table
+                .load(Ordering::Acquire)
+                .as_ref()
+                .is_some_and(|t| ptr::eq(&**t, tab))
+        {
             let sc = self.size_ctl.load(Ordering::Acquire);
-            if (sc >> RESIZE_STAMP_SHIFT) != rs
-                || sc == rs + 1
-                || sc == rs + MAX_RESIZERS
+            if sc >= 0
+                || !can_help_resize(sc, tab.len())
                 || self.transfer_index.load(Ordering::Acquire) <= 0
             {
                 return;
@@ -665,6 +1787,8 @@ where
         }
     }
     /// Moves and/or copies the nodes in each bin to new table. See above for explanation.
+    /// When the next table is half as large, as started by `shrink_to_fit`, pairs of bins are
+    /// merged instead, see `merge_bins`.
     unsafe fn transfer(
         &self,
         tab: &[BaseNode<K, V>],
@@ -673,8 +1797,8 @@ where
     ) {
         let n = tab.len();
         let mut stride = if NCPU > 1 { (n >> 3) / NCPU } else { n } as isize;
-        if stride < MIN_TRANSFER_STRIDE {
-            stride = MIN_TRANSFER_STRIDE; // subdivide range
+        if stride < self.min_transfer_stride {
+            stride = self.min_transfer_stride; // subdivide range
         }
         let size_ctl = &self.size_ctl;
         let next_table = &self.next_table;
@@ -705,6 +1829,9 @@ where
         let mut i = 0;
         let mut bound = 0;
         let n = n as isize;
+        let shrinking = nextn < n;
+        // indices handed out by transfer_index, one per pair of bins when shrinking
+        let bins = if shrinking { nextn } else { n };
         loop {
             while advance {
                 i -= 1;
@@ -733,12 +1860,13 @@ where
                     break;
                 }
             }
-            if i < 0 || i >= n || i + n >= nextn {
+            if i < 0 || i >= bins {
                 if finishing {
                     let next_table_ptr = next_table.swap(ptr::null_mut(), Ordering::AcqRel);
                     let old_tab_ptr = self.table.swap(next_table_ptr, Ordering::AcqRel);
                     guard.defer_destroy(old_tab_ptr);
-                    size_ctl.store((n << 1) - (n >> 1), Ordering::Release);
+                    size_ctl.store(nextn - (nextn >> 2), Ordering::Release);
+                    self.counters.resized();
                     return;
                 }
                 let sc = size_ctl.fetch_add(-1, Ordering::AcqRel);
@@ -747,7 +1875,11 @@ where
                 }
                 advance = true;
                 finishing = true;
-                i = n; // recheck before commit
+                i = bins; // recheck before commit
+                continue;
+            }
+            if shrinking {
+                advance = self.merge_bins(tab, next_tab, i as usize, fwd, guard);
                 continue;
             }
             let tab_at = &tab[i as usize];
@@ -759,7 +1891,7 @@ where
                     continue;
                 }
                 let n = n as usize;
-                let mutex_guard = tab_at.lock.lock();
+                let mutex_guard = tab_at.lock();
                 if tab_at_node.load(Ordering::Acquire) == f_ptr {
                     match f {
                         NodeEnums::Node(f) => {
@@ -769,17 +1901,11 @@ where
                             loop {
                                 let h = p.hash;
                                 if h & n == 0 {
-                                    ln = if let Some(ln) = ln {
-                                        Some(Node::new_next(h, p.key, p.val, ln.into_box()))
-                                    } else {
-                                        Some(Node::new(h, p.key, p.val))
-                                    }
+                                    let next = ln.map_or(ptr::null_mut(), Node::into_box);
+                                    ln = Some(p.moved(next));
                                 } else {
-                                    hn = if let Some(hn) = hn {
-                                        Some(Node::new_next(h, p.key, p.val, hn.into_box()))
-                                    } else {
-                                        Some(Node::new(h, p.key, p.val))
-                                    }
+                                    let next = hn.map_or(ptr::null_mut(), Node::into_box);
+                                    hn = Some(p.moved(next));
                                 }
                                 let next = p.next.load(Ordering::Acquire);
                                 if let Some(next) = next.as_ref() {
@@ -798,10 +1924,10 @@ where
                                     .node
                                     .store(NodeEnums::Node(hn).into_box(), Ordering::Release);
                             }
-                            let old = tab_at.node.swap(
-                                NodeEnums::ForwardingNode(fwd.clone()).into_box(),
-                                Ordering::AcqRel,
-                            );
+                            let old = tab_at
+                                .node
+                                .swap(NodeEnums::ForwardingNode(fwd).into_box(), Ordering::AcqRel);
+                            Self::retire_chain(f.next.load(Ordering::Acquire), guard);
                             guard.defer_destroy(old);
                             advance = true;
                         }
@@ -815,9 +1941,8 @@ where
                             let mut hi_tail = ptr::null_mut::<TreeNode<K, V>>();
                             while let Some(e) = e_ptr.as_ref() {
                                 let h = e.hash;
-                                let ek = e.key;
-                                let ev = e.val;
-                                let p = TreeNode::new(Node::new(h, ek, ev).into_box()).into_box();
+                                let p =
+                                    TreeNode::new(e.moved(ptr::null_mut()).into_box()).into_box();
                                 if (h & n) == 0 {
                                     if lo_tail.is_null() {
                                         lo = p;
@@ -832,12 +1957,8 @@ where
                                     if hi_tail.is_null() {
                                         hi = p;
                                     } else {
-                                        (*(*p).node)
-                                            .prev
-                                            .store((*hi_tail).node.clone(), Ordering::Release);
-                                        (*(*hi_tail).node)
-                                            .next
-                                            .store((*p).node.clone(), Ordering::Release);
+                                        (*(*p).node).prev.store((*hi_tail).node, Ordering::Release);
+                                        (*(*hi_tail).node).next.store((*p).node, Ordering::Release);
                                         (*hi_tail).right = p;
                                     }
                                     hi_tail = p;
@@ -845,28 +1966,32 @@ where
                                 }
                                 e_ptr = e.next.load(Ordering::Acquire);
                             }
-                            let ln = if lc < UNTREEIFY_THRESHOLD {
+                            let ln = if lc < self.untreeify_threshold {
                                 if lo.is_null() {
                                     None
                                 } else {
                                     //需要回收当前节点
                                     guard.defer_destroy((*lo).node);
-                                    Some(NodeEnums::Node(Self::untreeify(&*lo)))
+                                    let ln = self.untreeify(&*(*lo).node);
+                                    drop(Box::from_raw(lo));
+                                    Some(NodeEnums::Node(ln))
                                 }
                             } else {
                                 if lo.is_null() {
                                     None
                                 } else {
-                                    Some(NodeEnums::TreeBin(TreeBin::new(lo)))
+                                    Some(NodeEnums::TreeBin(TreeBin::new(lo, self.key_cmp)))
                                 }
                             };
                             let hn = if hi.is_null() {
                                 None
-                            } else if hc < UNTREEIFY_THRESHOLD {
+                            } else if hc < self.untreeify_threshold {
                                 guard.defer_destroy((*hi).node);
-                                Some(NodeEnums::Node(Self::untreeify(&*hi)))
+                                let hn = self.untreeify(&*(*hi).node);
+                                drop(Box::from_raw(hi));
+                                Some(NodeEnums::Node(hn))
                             } else {
-                                Some(NodeEnums::TreeBin(TreeBin::new(hi)))
+                                Some(NodeEnums::TreeBin(TreeBin::new(hi, self.key_cmp)))
                             };
                             if let Some(ln) = ln {
                                 next_tab[i as usize]
@@ -882,6 +2007,7 @@ where
                             let old = tab_at
                                 .node
                                 .swap(NodeEnums::ForwardingNode(fwd).into_box(), Ordering::AcqRel);
+                            Self::retire_chain(t.first.load(Ordering::Acquire), guard);
                             guard.defer_destroy(old);
                             advance = true;
                         }
@@ -901,16 +2027,188 @@ where
             }
         }
     }
+    /// Moves bins `i` and `i + next_tab.len()` of `tab` into bin `i` of `next_tab`, which is
+    /// half as large. Both bins stay locked until they are forwarded, so writers wait for the
+    /// merged bin instead of racing with the copy.
+    /// Returns false if an empty bin was filled before it could be claimed, to be retried.
+    unsafe fn merge_bins(
+        &self,
+        tab: &[BaseNode<K, V>],
+        next_tab: &[BaseNode<K, V>],
+        i: usize,
+        fwd: ForwardingNode<K, V>,
+        guard: &Guard,
+    ) -> bool {
+        let pair = [&tab[i], &tab[i + next_tab.len()]];
+        let _locks = pair.map(|b| b.lock());
+        // the chains to copy, and whether they come from tree bins
+        let mut chains = [(ptr::null_mut::<Node<K, V>>(), false); 2];
+        let mut moved = 0;
+        let mut reserved = false;
+        for (b, chain) in pair.iter().zip(&mut chains) {
+            match b.node.load(Ordering::Acquire).as_ref() {
+                None => {
+                    // empty bins are filled without locking, so reserve them until both are
+                    // forwarded
+                    let r = NodeEnums::ReservationNode(ReservationNode::new()).into_box();
+                    if b.node
+                        .compare_exchange(ptr::null_mut(), r, Ordering::AcqRel, Ordering::Relaxed)
+                        .is_err()
+                    {
+                        drop(Box::from_raw(r));
+                        if reserved {
+                            let r = pair[0].node.swap(ptr::null_mut(), Ordering::AcqRel);
+                            guard.defer_destroy(r);
+                        }
+                        return false;
+                    }
+                    reserved = true;
+                }
+                Some(NodeEnums::Node(head)) => *chain = (head as *const _ as *mut _, false),
+                Some(NodeEnums::TreeBin(t)) => *chain = (t.first.load(Ordering::Acquire), true),
+                // already merged, seen again by the final recheck
+                Some(NodeEnums::ForwardingNode(_)) => moved += 1,
+                Some(NodeEnums::ReservationNode(_)) => {
+                    unreachable!("reservations are only held under the bin lock")
+                }
+            }
+        }
+        if moved > 0 {
+            debug_assert_eq!(moved, 2, "bins are forwarded in pairs");
+            return true;
+        }
+        let tree = chains.iter().any(|&(_, tree)| tree);
+        let nodes = chains.iter().flat_map(|&(e, _)| chain(e));
+        let bin = self.copy_bin(nodes, tree);
+        if let Some(bin) = bin {
+            next_tab[i].node.store(bin.into_box(), Ordering::Release);
+        }
+        for (b, &(e, tree)) in pair.iter().zip(&chains) {
+            let old = b
+                .node
+                .swap(NodeEnums::ForwardingNode(fwd).into_box(), Ordering::AcqRel);
+            if tree {
+                Self::retire_chain(e, guard);
+            } else if let Some(head) = e.as_ref() {
+                // the head of a linked bin lives inside the bin box
+                Self::retire_chain(head.next.load(Ordering::Acquire), guard);
+            }
+            guard.defer_destroy(old);
+        }
+        true
+    }
+    /// Returns a bin holding copies of `nodes`, which take over their keys and values, or
+    /// `None` if there are none. Nodes from a tree bin stay a tree unless fewer than the
+    /// untreeify threshold remain.
+    pub(crate) unsafe fn copy_bin<'n, I>(&self, nodes: I, tree: bool) -> Option<NodeEnums<K, V>>
+    where
+        I: Iterator<Item = &'n Node<K, V>> + Clone,
+        K: 'n,
+        V: 'n,
+    {
+        let count = nodes.clone().count();
+        if count == 0 {
+            None
+        } else if tree && count >= self.untreeify_threshold {
+            let mut hd = ptr::null_mut::<TreeNode<K, V>>();
+            let mut tail = ptr::null_mut::<TreeNode<K, V>>();
+            for node in nodes {
+                let p = TreeNode::new(node.moved(ptr::null_mut()).into_box()).into_box();
+                if tail.is_null() {
+                    hd = p;
+                } else {
+                    (*(*p).node).prev.store((*tail).node, Ordering::Release);
+                    (*(*tail).node).next.store((*p).node, Ordering::Release);
+                    (*tail).right = p;
+                }
+                tail = p;
+            }
+            Some(NodeEnums::TreeBin(TreeBin::new(hd, self.key_cmp)))
+        } else {
+            if tree {
+                self.counters.untreeified();
+            }
+            let mut ln: Option<Node<K, V>> = None;
+            for node in nodes {
+                let next = ln.map_or(ptr::null_mut(), Node::into_box);
+                ln = Some(node.moved(next));
+            }
+            ln.map(NodeEnums::Node)
+        }
+    }
+    /// Replaces the bin `p` stored in `f` by a copy without the nodes for which `remove`
+    /// returns true, if there are any. `remove` is called once for every node, and `keep` is
+    /// scratch space. The caller must hold the bin lock, or have exclusive access to the map.
+    /// Returns the number of entries removed, which are reclaimed through `r`.
+    pub(crate) unsafe fn retain_bin<R, F>(
+        &self,
+        f: &BaseNode<K, V>,
+        p: *mut NodeEnums<K, V>,
+        mut remove: F,
+        keep: &mut Vec<bool>,
+        r: &R,
+    ) -> usize
+    where
+        R: Reclaim,
+        F: FnMut(&Node<K, V>) -> bool,
+    {
+        let (first, tree) = match p.as_ref() {
+            Some(NodeEnums::Node(head)) => (head as *const _ as *mut Node<K, V>, false),
+            Some(NodeEnums::TreeBin(t)) => (t.first.load(Ordering::Acquire), true),
+            _ => return 0,
+        };
+        keep.clear();
+        keep.extend(chain(first).map(|e| !remove(e)));
+        let removed = keep.iter().filter(|&&k| !k).count();
+        if removed == 0 {
+            return 0;
+        }
+        let kept = chain(first)
+            .zip(keep.iter())
+            .filter(|(_, &k)| k)
+            .map(|(e, _)| e);
+        let bin = self.copy_bin(kept, tree);
+        f.node.store(
+            bin.map_or(ptr::null_mut(), NodeEnums::into_box),
+            Ordering::Release,
+        );
+        let mut e = first;
+        for (j, &k) in keep.iter().enumerate() {
+            let next = (*e).next.load(Ordering::Acquire);
+            if !k {
+                r.reclaim((*e).val.load(Ordering::Acquire));
+            }
+            // the head of a linked bin lives inside the bin box
+            if tree || j > 0 {
+                if k {
+                    r.reclaim(e);
+                } else {
+                    Node::retire(e, r);
+                }
+            }
+            e = next;
+        }
+        if !tree && !keep[0] {
+            NodeEnums::retire(p, r);
+        } else {
+            r.reclaim(p);
+        }
+        removed
+    }
+    /// Retires the nodes chained from `e`, whose keys and values now belong to copies of them,
+    /// see `Node::moved`.
+    unsafe fn retire_chain(mut e: *mut Node<K, V>, guard: &Guard) {
+        while let Some(node) = e.as_ref() {
+            let next = node.next.load(Ordering::Acquire);
+            guard.defer_destroy(e);
+            e = next;
+        }
+    }
     /// Returns a list on non-TreeNodes replacing those in given list.
     #[inline]
-    unsafe fn untreeify(b: &TreeNode<K, V>) -> Node<K, V> {
-        let node = &*b.node;
-        Node::new_next(
-            node.hash,
-            node.key,
-            node.val,
-            node.next.load(Ordering::Relaxed),
-        )
+    unsafe fn untreeify(&self, node: &Node<K, V>) -> Node<K, V> {
+        self.counters.untreeified();
+        node.moved(node.next.load(Ordering::Relaxed))
     }
     fn new_tab(n: usize) -> thread::Result<*mut Box<[BaseNode<K, V>]>> {
         panic::catch_unwind(|| {
@@ -920,6 +2218,48 @@ where
     }
 }
 
+/// Reads the number of CPUS once, before the first map is created.
+fn init_ncpu() {
+    INIT.call_once(|| unsafe {
+        let n = thread::available_parallelism()
+            .map(|v| v.get())
+            .unwrap_or(1);
+        if n == 0 {
+            NCPU = 1;
+        } else {
+            NCPU = n;
+        }
+    });
+}
+
+/// Returns the number of CPUs.
+pub(crate) fn ncpu() -> usize {
+    init_ncpu();
+    unsafe { NCPU }
+}
+
+/// Returns the nodes chained from `e`.
+pub(crate) unsafe fn chain<'n, K: 'n, V: 'n>(
+    e: *mut Node<K, V>,
+) -> impl Iterator<Item = &'n Node<K, V>> + Clone {
+    iter::successors(e.as_ref(), |node| {
+        node.next.load(Ordering::Acquire).as_ref()
+    })
+}
+
+/// Computes the batch value of a bulk task over `n` elements, see `batch_for`.
+pub(crate) fn batch_for_size(n: usize, b: usize) -> usize {
+    if b == usize::MAX || n <= 1 || n < b {
+        return 0;
+    }
+    let sp = unsafe { NCPU } << 2;
+    if b == 0 || n / b >= sp {
+        sp
+    } else {
+        n / b
+    }
+}
+
 /// Returns a power of two table size for the given desired capacity. See Hackers Delight, sec 3.2
 fn table_size_for(c: usize) -> usize {
     let mut n = c - 1;
@@ -941,6 +2281,17 @@ fn resize_stamp(n: isize) -> isize {
     number_of_leading_zeros(n) | (1 << (RESIZE_STAMP_BITS - 1))
 }
 
+/// Returns whether a thread may help with the resize of a table of length `n` recorded in the
+/// negative size control `sc`: its stamp must be the one of `n`, and the resize must neither
+/// be finishing nor already have the maximum number of resizers.
+/// The stamp sets the sign bit of `sc`, so it is shifted down unsigned like `>>>` in the JDK.
+fn can_help_resize(sc: isize, n: usize) -> bool {
+    let rs = resize_stamp(n as isize);
+    (sc as usize >> RESIZE_STAMP_SHIFT) as isize == rs
+        && sc != (rs << RESIZE_STAMP_SHIFT) + 1
+        && sc != (rs << RESIZE_STAMP_SHIFT) + MAX_RESIZERS
+}
+
 /// Returns the number of zero bits preceding the highest-order
 /// ("leftmost") one-bit in the two's complement binary representation
 /// of the specified int value. Returns 32 if the
@@ -978,5 +2329,210 @@ fn number_of_leading_zeros(mut i: isize) -> isize {
         i <<= 2;
     }
     n -= i >> 31;
-    return n;
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::concurrent_hash_map::test_util::Ids;
+
+    #[test]
+    fn recursive_update_panics() {
+        let map = ConcurrentHashMap::new();
+        map.insert(1, 1);
+        for absent in [false, true] {
+            let key = if absent { 2 } else { 1 };
+            let r = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.compute(key, |_, _| {
+                    map.insert(key, 0);
+                    Some(3)
+                });
+            }));
+            let e = r.unwrap_err();
+            assert_eq!(e.downcast_ref::<&str>(), Some(&"Recursive update"));
+        }
+        // the bins were released and left as they were
+        assert_eq!(map.get(&1).as_deref(), Some(&1));
+        assert!(map.get(&2).is_none());
+        map.insert(2, 2);
+        assert_eq!(map.size(), 2);
+    }
+
+    #[test]
+    fn reserve_during_resizes() {
+        let map = ConcurrentHashMap::new();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..10_000 {
+                        map.insert(t * 10_000 + i, i);
+                    }
+                });
+            }
+            s.spawn(|| {
+                for i in 1..=16 {
+                    map.reserve(i * 2_500);
+                }
+            });
+        });
+        assert_eq!(map.size(), 40_000);
+        let guard = map.guard();
+        assert_eq!(map.iter(&guard).count(), 40_000);
+        assert!(map.get(&39_999).is_some());
+    }
+
+    #[test]
+    fn shrink_merges_tree_bins_across_the_untreeify_threshold() {
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        let fillers = 0..1_000;
+        for i in fillers.clone() {
+            map.insert(i, i);
+        }
+        let n = map.stats().table_len as u64;
+        // `len` keys in bin `bin` of the grown table
+        let bin = |bin: u64, len: u64| (1..=len).map(move |k| k * n + bin);
+        let half = n / 2;
+        let small_tree = |b: u64| {
+            for k in bin(b, 9) {
+                map.insert(k, k);
+            }
+            for k in bin(b, 9).skip(5) {
+                map.remove(&k);
+            }
+        };
+        for i in fillers {
+            map.remove(&i);
+        }
+        let tree_bins = map.stats().tree_bins;
+        // a tree of 5 merged with an empty bin is untreeified, one merged with a node is not
+        small_tree(1);
+        small_tree(2);
+        map.insert(n + 2 + half, 0);
+        // neither are two merged trees
+        for k in bin(3, 9).chain(bin(3 + half, 9)) {
+            map.insert(k, k);
+        }
+        // lists are not treeified
+        for k in bin(4, 4).chain(bin(4 + half, 4)) {
+            map.insert(k, k);
+        }
+        assert_eq!(map.stats().tree_bins, tree_bins + 4);
+        map.shrink_to_fit();
+        let stats = map.stats();
+        assert!(stats.table_len < n as usize);
+        assert_eq!(stats.tree_bins, 2);
+        assert_eq!(stats.entries, 5 + 6 + 18 + 8);
+        for k in bin(1, 5)
+            .chain(bin(2, 5))
+            .chain(bin(3, 9))
+            .chain(bin(3 + half, 9))
+            .chain(bin(4, 4))
+            .chain(bin(4 + half, 4))
+        {
+            assert_eq!(map.get(&k).as_deref(), Some(&k));
+        }
+        assert_eq!(map.get(&(n + 2 + half)).as_deref(), Some(&0));
+    }
+
+    #[test]
+    fn operations_during_shrinks() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..20_000 {
+            map.insert(i, i);
+        }
+        for i in 1_000..20_000 {
+            map.remove(&i);
+        }
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..3 {
+                    map.shrink_to_fit();
+                    if i < 2 {
+                        // grow again, racing the other threads
+                        map.reserve(20_000);
+                    }
+                }
+                done.store(true, Ordering::Release);
+            });
+            s.spawn(|| {
+                while !done.load(Ordering::Acquire) {
+                    for i in 0..1_000 {
+                        assert_eq!(map.get(&i).as_deref(), Some(&i));
+                    }
+                }
+            });
+            s.spawn(|| {
+                while !done.load(Ordering::Acquire) {
+                    let guard = map.guard();
+                    let mut seen = vec![false; 1_000];
+                    for (&k, _) in map.iter(&guard) {
+                        if k < 1_000 {
+                            assert!(!seen[k], "{} seen twice", k);
+                            seen[k] = true;
+                        }
+                    }
+                    assert!(seen.iter().all(|&s| s));
+                }
+            });
+            s.spawn(|| {
+                let mut i = 20_000;
+                while !done.load(Ordering::Acquire) {
+                    map.insert(i, i);
+                    if i % 2 == 0 {
+                        map.remove(&(i - 1));
+                    }
+                    i += 1;
+                }
+            });
+        });
+        let guard = map.guard();
+        assert_eq!(map.iter(&guard).count(), map.size());
+        for i in 0..1_000 {
+            assert_eq!(map.get(&i).as_deref(), Some(&i));
+        }
+    }
+
+    #[test]
+    fn grows_racing_shrinks() {
+        let map = ConcurrentHashMap::new();
+        thread::scope(|s| {
+            for t in 0..2 {
+                let map = &map;
+                s.spawn(move || {
+                    for round in 0..20 {
+                        let keys = (0..2_000).map(|i| t * 1_000_000 + round * 2_000 + i);
+                        for k in keys.clone() {
+                            map.insert(k, k);
+                        }
+                        // keep one key of every round
+                        for k in keys.skip(1) {
+                            assert_eq!(map.remove(&k).as_deref(), Some(&k));
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..200 {
+                    map.shrink_to_fit();
+                    thread::yield_now();
+                }
+            });
+        });
+        assert_eq!(map.size(), 40);
+        map.shrink_to_fit();
+        // the smallest table for 40 entries at a load factor of 0.75
+        assert_eq!(map.stats().table_len, 64);
+        for t in 0..2 {
+            for round in 0..20 {
+                let k = t * 1_000_000 + round * 2_000;
+                assert_eq!(map.get(&k).as_deref(), Some(&k));
+            }
+        }
+    }
 }
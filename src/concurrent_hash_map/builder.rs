@@ -0,0 +1,323 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+use crate::concurrent_hash_map::base::{
+    ConcurrentHashMap, DEFAULT_CAPACITY, LOAD_FACTOR, MIN_TRANSFER_STRIDE, MIN_TREEIFY_CAPACITY,
+    TREEIFY_THRESHOLD, UNTREEIFY_THRESHOLD,
+};
+use crate::concurrent_hash_map::bulk::{Executor, ScopedThreads};
+use crate::concurrent_hash_map::tree::KeyCmp;
+use crate::ebr::collector::Collector;
+
+/// A builder for a `ConcurrentHashMap`, for the settings its constructors do not cover.
+///
+/// Every setting defaults to what `ConcurrentHashMap::new` uses. `build` checks the
+/// constraints documented on the corresponding constants, and panics if one does not hold.
+pub struct ConcurrentHashMapBuilder<K, V, S = RandomState> {
+    hash_builder: S,
+    initial_capacity: usize,
+    load_factor: f32,
+    concurrency_level: usize,
+    treeify_threshold: usize,
+    untreeify_threshold: usize,
+    min_treeify_capacity: usize,
+    min_transfer_stride: usize,
+    key_cmp: Option<KeyCmp<K>>,
+    collector: Collector,
+    executor: Box<dyn Executor>,
+    marker: PhantomData<V>,
+}
+
+impl<K, V> ConcurrentHashMap<K, V, RandomState>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+{
+    /// Returns a builder for a map with custom settings.
+    pub fn builder() -> ConcurrentHashMapBuilder<K, V> {
+        ConcurrentHashMapBuilder::new()
+    }
+}
+
+impl<K, V> ConcurrentHashMapBuilder<K, V, RandomState> {
+    pub fn new() -> ConcurrentHashMapBuilder<K, V> {
+        Self {
+            hash_builder: RandomState::new(),
+            initial_capacity: 0,
+            load_factor: LOAD_FACTOR,
+            concurrency_level: 1,
+            treeify_threshold: TREEIFY_THRESHOLD,
+            untreeify_threshold: UNTREEIFY_THRESHOLD,
+            min_treeify_capacity: MIN_TREEIFY_CAPACITY,
+            min_transfer_stride: MIN_TRANSFER_STRIDE as usize,
+            key_cmp: None,
+            collector: Collector::new(),
+            executor: Box::new(ScopedThreads),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Default for ConcurrentHashMapBuilder<K, V, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> ConcurrentHashMapBuilder<K, V, S> {
+    /// Sets the hasher used to hash keys.
+    pub fn hasher<T>(self, hash_builder: T) -> ConcurrentHashMapBuilder<K, V, T> {
+        ConcurrentHashMapBuilder {
+            hash_builder,
+            initial_capacity: self.initial_capacity,
+            load_factor: self.load_factor,
+            concurrency_level: self.concurrency_level,
+            treeify_threshold: self.treeify_threshold,
+            untreeify_threshold: self.untreeify_threshold,
+            min_treeify_capacity: self.min_treeify_capacity,
+            min_transfer_stride: self.min_transfer_stride,
+            key_cmp: self.key_cmp,
+            collector: self.collector,
+            executor: self.executor,
+            marker: PhantomData,
+        }
+    }
+    /// Sets the number of elements the initial table accommodates, given the load factor and
+    /// concurrency level. See `ConcurrentHashMap::with_concurrency_level`.
+    pub fn initial_capacity(mut self, initial_capacity: usize) -> Self {
+        self.initial_capacity = initial_capacity;
+        self
+    }
+    /// Sets the load factor used to size the initial table. As in the JDK, it does not change
+    /// the threshold for later resizes. Must be positive.
+    pub fn load_factor(mut self, load_factor: f32) -> Self {
+        self.load_factor = load_factor;
+        self
+    }
+    /// Sets the estimated number of concurrently updating threads, used to size the initial
+    /// table. Must be positive.
+    pub fn concurrency_level(mut self, concurrency_level: usize) -> Self {
+        self.concurrency_level = concurrency_level;
+        self
+    }
+    /// Sets the number of nodes a bin must hold for it to be converted to a tree. Must be
+    /// greater than 2.
+    pub fn treeify_threshold(mut self, treeify_threshold: usize) -> Self {
+        self.treeify_threshold = treeify_threshold;
+        self
+    }
+    /// Sets the number of nodes below which a tree bin split by a resize is converted back
+    /// to a list. Must be less than the treeify threshold, and at most 6.
+    pub fn untreeify_threshold(mut self, untreeify_threshold: usize) -> Self {
+        self.untreeify_threshold = untreeify_threshold;
+        self
+    }
+    /// Sets the smallest table capacity for which bins may be treeified, below it the table
+    /// is resized instead. Must be at least 4 * the treeify threshold.
+    pub fn min_treeify_capacity(mut self, min_treeify_capacity: usize) -> Self {
+        self.min_treeify_capacity = min_treeify_capacity;
+        self
+    }
+    /// Sets the minimum number of bins a resizing thread claims at a time. Must be at least
+    /// 16, the default capacity.
+    pub fn min_transfer_stride(mut self, min_transfer_stride: usize) -> Self {
+        self.min_transfer_stride = min_transfer_stride;
+        self
+    }
+    /// Sets the collector that reclaims the memory of removed entries, for example one created
+    /// with `Collector::with_gc_interval`.
+    pub fn collector(mut self, collector: Collector) -> Self {
+        self.collector = collector;
+        self
+    }
+    /// Sets the executor running the batches of bulk operations.
+    pub fn executor<E: Executor + 'static>(mut self, executor: E) -> Self {
+        self.executor = Box::new(executor);
+        self
+    }
+}
+
+impl<K, V, S> ConcurrentHashMapBuilder<K, V, S>
+where
+    K: Ord,
+{
+    /// Orders keys with equal hashes in tree bins by `Ord`. See
+    /// `ConcurrentHashMap::with_ordered_bins_and_hasher`.
+    pub fn ordered_bins(mut self) -> Self {
+        self.key_cmp = Some(K::cmp);
+        self
+    }
+}
+
+impl<K, V, S> ConcurrentHashMapBuilder<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Creates the map.
+    /// Panics if the settings are invalid.
+    pub fn build(self) -> ConcurrentHashMap<K, V, S> {
+        assert!(self.load_factor > 0.0, "load factor must be positive");
+        assert!(
+            self.concurrency_level > 0,
+            "concurrency level must be positive"
+        );
+        assert!(
+            self.treeify_threshold > 2,
+            "treeify threshold must be greater than 2"
+        );
+        assert!(
+            self.untreeify_threshold < self.treeify_threshold && self.untreeify_threshold <= 6,
+            "untreeify threshold must be less than the treeify threshold, and at most 6"
+        );
+        assert!(
+            self.min_treeify_capacity >= self.treeify_threshold.saturating_mul(4),
+            "min treeify capacity must be at least 4 * treeify threshold"
+        );
+        assert!(
+            self.min_transfer_stride >= DEFAULT_CAPACITY
+                && self.min_transfer_stride <= isize::MAX as usize,
+            "min transfer stride must be at least the default capacity"
+        );
+        let mut map = if self.initial_capacity == 0 && self.concurrency_level == 1 {
+            // nothing to size for, use the default capacity like `new`
+            ConcurrentHashMap::with_hasher(self.hash_builder)
+        } else {
+            ConcurrentHashMap::with_concurrency_level_and_hasher(
+                self.initial_capacity,
+                self.load_factor,
+                self.concurrency_level,
+                self.hash_builder,
+            )
+        };
+        map.collector = self.collector;
+        map.executor = self.executor;
+        map.key_cmp = self.key_cmp;
+        map.treeify_threshold = self.treeify_threshold;
+        map.untreeify_threshold = self.untreeify_threshold;
+        map.min_treeify_capacity = self.min_treeify_capacity;
+        map.min_transfer_stride = self.min_transfer_stride as isize;
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    use crate::concurrent_hash_map::test_util::{Counted, Ids};
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentHashMapBuilder, ConcurrentMap};
+    use crate::ebr::collector::Collector;
+
+    fn builder() -> ConcurrentHashMapBuilder<u64, u64, Ids> {
+        ConcurrentHashMap::builder().hasher(Ids::default())
+    }
+
+    #[test]
+    #[should_panic(expected = "load factor must be positive")]
+    fn rejects_a_zero_load_factor() {
+        builder().load_factor(0.0).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "concurrency level must be positive")]
+    fn rejects_a_zero_concurrency_level() {
+        builder().concurrency_level(0).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "treeify threshold must be greater than 2")]
+    fn rejects_a_small_treeify_threshold() {
+        builder()
+            .treeify_threshold(2)
+            .untreeify_threshold(1)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "untreeify threshold must be less than the treeify threshold")]
+    fn rejects_an_untreeify_threshold_reaching_the_treeify_threshold() {
+        builder()
+            .treeify_threshold(4)
+            .untreeify_threshold(4)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "untreeify threshold must be less than the treeify threshold")]
+    fn rejects_an_untreeify_threshold_above_6() {
+        builder()
+            .treeify_threshold(10)
+            .untreeify_threshold(7)
+            .min_treeify_capacity(64)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "min treeify capacity must be at least 4 * treeify threshold")]
+    fn rejects_a_small_min_treeify_capacity() {
+        builder().min_treeify_capacity(31).build();
+    }
+
+    #[test]
+    #[should_panic(expected = "min transfer stride must be at least the default capacity")]
+    fn rejects_a_small_min_transfer_stride() {
+        builder().min_transfer_stride(15).build();
+    }
+
+    #[test]
+    fn concurrency_level_sizes_the_table() {
+        let map = builder().concurrency_level(100).build();
+        map.insert(0, 0);
+        // 100 / 0.75 entries round up to 256 bins, like in the JDK
+        assert_eq!(map.stats().table_len, 256);
+        let map = builder().initial_capacity(100).load_factor(0.5).build();
+        map.insert(0, 0);
+        assert_eq!(map.stats().table_len, 256);
+    }
+
+    #[test]
+    fn treeify_thresholds_reach_the_map() {
+        let map = builder()
+            .treeify_threshold(4)
+            .untreeify_threshold(2)
+            .min_treeify_capacity(16)
+            .build();
+        // 5 keys in bin 0 of the default table, one more than the threshold
+        for i in 0..5 {
+            map.insert(i * 16, i);
+        }
+        let stats = map.stats();
+        assert_eq!((stats.table_len, stats.tree_bins), (16, 1));
+        // split into bins of 2, 1, 1 and 1 keys, of which only the first is not below the
+        // untreeify threshold
+        map.reserve(20);
+        let stats = map.stats();
+        assert_eq!((stats.table_len, stats.tree_bins), (64, 1));
+        let map = builder().min_treeify_capacity(256).build();
+        for i in 0..9 {
+            map.insert(i * 256, i);
+        }
+        assert_eq!(map.stats().tree_bins, 0);
+    }
+
+    #[test]
+    fn collector_settings_reach_the_map() {
+        static LIVE: AtomicIsize = AtomicIsize::new(0);
+        let map = ConcurrentHashMap::builder()
+            .collector(Collector::with_gc_interval(1))
+            .build();
+        map.insert(0, Counted::new(0, &LIVE));
+        map.remove(&0);
+        // every dropped guard tries to advance the epoch, so the value is soon freed
+        for _ in 0..1024 {
+            if LIVE.load(Ordering::Relaxed) == 0 {
+                return;
+            }
+            map.get(&0);
+        }
+        panic!("the removed value was not freed");
+    }
+}
@@ -0,0 +1,329 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use parking_lot::Mutex;
+
+use crate::concurrent_hash_map::base::{ncpu, ConcurrentHashMap};
+use crate::concurrent_hash_map::iter::Traverser;
+
+/// A batch of work handed to an `Executor`, borrowing from the caller for `'s`.
+pub type Task<'s> = Box<dyn FnOnce() + Send + 's>;
+
+/// Runs the batches of a bulk operation.
+///
+/// `execute` must run every task to completion before returning, since the tasks borrow the
+/// map, the caller's closures and the guard protecting the traversed nodes.
+pub trait Executor: Send + Sync {
+    fn execute<'s>(&self, tasks: Vec<Task<'s>>);
+}
+
+/// The default executor. Runs the tasks on at most as many scoped threads as there are CPUs,
+/// the calling thread being one of them, each taking the next task from a shared queue until
+/// none is left.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ScopedThreads;
+
+impl Executor for ScopedThreads {
+    fn execute<'s>(&self, tasks: Vec<Task<'s>>) {
+        let workers = tasks.len().min(ncpu());
+        let queue = Mutex::new(tasks.into_iter());
+        let work = || loop {
+            let task = queue.lock().next();
+            match task {
+                Some(task) => task(),
+                None => break,
+            }
+        };
+        thread::scope(|s| {
+            for _ in 1..workers {
+                s.spawn(work);
+            }
+            work();
+        });
+    }
+}
+
+/// A traverser handed to another thread. The nodes it reaches stay protected by the guard
+/// of the calling thread, which waits for every task before unpinning.
+struct Batch<'g, K, V>(Traverser<'g, K, V>);
+
+unsafe impl<'g, K: Send + Sync, V: Send + Sync> Send for Batch<'g, K, V> {}
+
+/// Bulk operations, in the manner of the JDK's forEach, search and reduce methods.
+///
+/// Each takes a `parallelism_threshold`, the (estimated) number of elements needed for the
+/// operation to be executed in parallel: `usize::MAX` runs it sequentially on the calling
+/// thread, and `1` splits it across as many batches as the parallelism allows. The table is
+/// divided into ranges of bins like the JDK's `BulkTask`, and the ranges are run by the
+/// executor of the map. Like `iter`, the operations are weakly consistent, and the functions
+/// should not write to the map.
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    /// Runs `task` over every range of bins, returning one result per range.
+    fn bulk<R, F>(&self, parallelism_threshold: usize, task: F) -> Vec<R>
+    where
+        R: Send,
+        F: Fn(Traverser<'_, K, V>) -> R + Sync,
+    {
+        let guard = self.guard();
+        let mut its = self.bulk_traversers(parallelism_threshold, &guard);
+        if its.len() == 1 {
+            return vec![task(its.pop().unwrap())];
+        }
+        let mut results: Vec<Option<R>> = its.iter().map(|_| None).collect();
+        let task = &task;
+        let tasks = its
+            .into_iter()
+            .map(Batch)
+            .zip(results.iter_mut())
+            .map(|(batch, result)| -> Task<'_> {
+                Box::new(move || {
+                    let batch = batch;
+                    *result = Some(task(batch.0));
+                })
+            })
+            .collect();
+        self.executor().execute(tasks);
+        results
+            .into_iter()
+            .map(|r| r.expect("executor did not run every task"))
+            .collect()
+    }
+    /// Performs the given action for each (key, value).
+    pub fn for_each<F>(&self, parallelism_threshold: usize, action: F)
+    where
+        F: Fn(&K, &V) + Sync,
+    {
+        self.bulk(parallelism_threshold, |mut it| {
+            while let Some(p) = it.advance() {
+                unsafe { action(&*p.key, &*p.val.load(Ordering::Acquire)) }
+            }
+        });
+    }
+    /// Performs the given action for each key.
+    pub fn for_each_key<F>(&self, parallelism_threshold: usize, action: F)
+    where
+        F: Fn(&K) + Sync,
+    {
+        self.for_each(parallelism_threshold, |k, _| action(k));
+    }
+    /// Performs the given action for each value.
+    pub fn for_each_value<F>(&self, parallelism_threshold: usize, action: F)
+    where
+        F: Fn(&V) + Sync,
+    {
+        self.for_each(parallelism_threshold, |_, v| action(v));
+    }
+    /// Returns a non-`None` result from applying the given search function on each (key,
+    /// value), or `None` if none. Upon success, further element processing is suppressed and
+    /// the results of any other parallel invocations of the search function are ignored.
+    pub fn search<U, F>(&self, parallelism_threshold: usize, search_function: F) -> Option<U>
+    where
+        U: Send,
+        F: Fn(&K, &V) -> Option<U> + Sync,
+    {
+        let found = AtomicBool::new(false);
+        self.bulk(parallelism_threshold, |mut it| {
+            while let Some(p) = it.advance() {
+                if found.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let u = unsafe { search_function(&*p.key, &*p.val.load(Ordering::Acquire)) };
+                if u.is_some() {
+                    found.store(true, Ordering::Relaxed);
+                    return u;
+                }
+            }
+            None
+        })
+        .into_iter()
+        .flatten()
+        .next()
+    }
+    /// Like `search`, applying the search function on each key.
+    pub fn search_keys<U, F>(&self, parallelism_threshold: usize, search_function: F) -> Option<U>
+    where
+        U: Send,
+        F: Fn(&K) -> Option<U> + Sync,
+    {
+        self.search(parallelism_threshold, |k, _| search_function(k))
+    }
+    /// Like `search`, applying the search function on each value.
+    pub fn search_values<U, F>(&self, parallelism_threshold: usize, search_function: F) -> Option<U>
+    where
+        U: Send,
+        F: Fn(&V) -> Option<U> + Sync,
+    {
+        self.search(parallelism_threshold, |_, v| search_function(v))
+    }
+    /// Returns the result of accumulating the given transformation of all (key, value) pairs
+    /// using the given reducer to combine values, or `None` if none. Elements the transformer
+    /// maps to `None` are skipped.
+    pub fn reduce<U, T, R>(
+        &self,
+        parallelism_threshold: usize,
+        transformer: T,
+        reducer: R,
+    ) -> Option<U>
+    where
+        U: Send,
+        T: Fn(&K, &V) -> Option<U> + Sync,
+        R: Fn(U, U) -> U + Sync,
+    {
+        let reduce = |a: Option<U>, b: Option<U>| match (a, b) {
+            (Some(a), Some(b)) => Some(reducer(a, b)),
+            (a, b) => a.or(b),
+        };
+        self.bulk(parallelism_threshold, |mut it| {
+            let mut r = None;
+            while let Some(p) = it.advance() {
+                r = reduce(r, unsafe {
+                    transformer(&*p.key, &*p.val.load(Ordering::Acquire))
+                });
+            }
+            r
+        })
+        .into_iter()
+        .fold(None, reduce)
+    }
+    /// Like `reduce`, accumulating the given transformation of all keys.
+    pub fn reduce_keys<U, T, R>(
+        &self,
+        parallelism_threshold: usize,
+        transformer: T,
+        reducer: R,
+    ) -> Option<U>
+    where
+        U: Send,
+        T: Fn(&K) -> Option<U> + Sync,
+        R: Fn(U, U) -> U + Sync,
+    {
+        self.reduce(parallelism_threshold, |k, _| transformer(k), reducer)
+    }
+    /// Like `reduce`, accumulating the given transformation of all values.
+    pub fn reduce_values<U, T, R>(
+        &self,
+        parallelism_threshold: usize,
+        transformer: T,
+        reducer: R,
+    ) -> Option<U>
+    where
+        U: Send,
+        T: Fn(&V) -> Option<U> + Sync,
+        R: Fn(U, U) -> U + Sync,
+    {
+        self.reduce(parallelism_threshold, |_, v| transformer(v), reducer)
+    }
+    /// Returns the result of accumulating the given transformation of all (key, value) pairs
+    /// using the given reducer to combine values, and the given basis as an identity value.
+    pub fn reduce_to_i64<T, R>(
+        &self,
+        parallelism_threshold: usize,
+        transformer: T,
+        basis: i64,
+        reducer: R,
+    ) -> i64
+    where
+        T: Fn(&K, &V) -> i64 + Sync,
+        R: Fn(i64, i64) -> i64 + Sync,
+    {
+        self.bulk(parallelism_threshold, |mut it| {
+            let mut r = basis;
+            while let Some(p) = it.advance() {
+                r = reducer(r, unsafe {
+                    transformer(&*p.key, &*p.val.load(Ordering::Acquire))
+                });
+            }
+            r
+        })
+        .into_iter()
+        .fold(basis, &reducer)
+    }
+    /// Like `reduce_to_i64`, accumulating the given transformation of all keys.
+    pub fn reduce_keys_to_i64<T, R>(
+        &self,
+        parallelism_threshold: usize,
+        transformer: T,
+        basis: i64,
+        reducer: R,
+    ) -> i64
+    where
+        T: Fn(&K) -> i64 + Sync,
+        R: Fn(i64, i64) -> i64 + Sync,
+    {
+        self.reduce_to_i64(parallelism_threshold, |k, _| transformer(k), basis, reducer)
+    }
+    /// Like `reduce_to_i64`, accumulating the given transformation of all values.
+    pub fn reduce_values_to_i64<T, R>(
+        &self,
+        parallelism_threshold: usize,
+        transformer: T,
+        basis: i64,
+        reducer: R,
+    ) -> i64
+    where
+        T: Fn(&V) -> i64 + Sync,
+        R: Fn(i64, i64) -> i64 + Sync,
+    {
+        self.reduce_to_i64(parallelism_threshold, |_, v| transformer(v), basis, reducer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::concurrent_hash_map::ConcurrentMap;
+
+    #[test]
+    fn scoped_threads_use_at_most_ncpu_threads() {
+        let n = ncpu() * 4 + 3;
+        let active = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let threads = Mutex::new(HashSet::new());
+        let ran = AtomicUsize::new(0);
+        let tasks = (0..n)
+            .map(|_| -> Task<'_> {
+                Box::new(|| {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    threads.lock().insert(thread::current().id());
+                    thread::sleep(Duration::from_millis(2));
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        ScopedThreads.execute(tasks);
+        assert_eq!(ran.load(Ordering::SeqCst), n);
+        assert!(most.load(Ordering::SeqCst) <= ncpu());
+        let threads = threads.into_inner();
+        assert!(threads.len() <= ncpu());
+        assert!(threads.contains(&thread::current().id()));
+    }
+
+    #[test]
+    fn bulk_operations_cover_every_entry() {
+        let map = ConcurrentHashMap::new();
+        map.extend_parallel(1, (0..10_000u64).map(|i| (i, i)));
+        assert_eq!(map.size(), 10_000);
+        let sum = AtomicUsize::new(0);
+        map.for_each(1, |_, v| {
+            sum.fetch_add(*v as usize, Ordering::Relaxed);
+        });
+        assert_eq!(sum.into_inner(), (0..10_000).sum::<usize>());
+        let found = map.search(1, |k, _| (*k == 4_321).then_some(*k));
+        assert_eq!(found, Some(4_321));
+    }
+}
@@ -0,0 +1,374 @@
+use std::hash::{BuildHasher, Hash};
+use std::ptr;
+use std::sync::atomic::Ordering;
+
+use crate::concurrent_hash_map::base::{ConcurrentHashMap, LockedBin, NodeEnums};
+use crate::concurrent_hash_map::map::Value;
+use crate::concurrent_hash_map::node::Node;
+use crate::concurrent_hash_map::tree::TreeNode;
+use crate::ebr::collector::Guard;
+
+/// A view into a single entry of a map, which may either be vacant or occupied.
+/// The bin of the entry stays locked until the entry is dropped or consumed, so other writers
+/// to that bin, and resizes, wait for it. Keep entries short lived, and do not write to the
+/// map from the same thread while holding one.
+pub enum Entry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+/// Where the node of an occupied entry sits in its bin.
+pub(crate) enum Slot<K, V> {
+    /// `pred` is null when `e` is the head of the bin.
+    Linked {
+        pred: *mut Node<K, V>,
+        e: *mut Node<K, V>,
+    },
+    Tree(*mut TreeNode<K, V>),
+}
+
+/// Where a vacant entry will link its node.
+pub(crate) enum Tail<K, V> {
+    /// The bin was empty and holds our reservation.
+    Reserved,
+    /// Append after `last`, the `bin_count`th node of the bin.
+    Linked {
+        last: *mut Node<K, V>,
+        bin_count: usize,
+    },
+    Tree,
+}
+
+/// A view into an occupied entry, holding the lock of its bin.
+pub struct OccupiedEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    // dropped first, so the bin is unlocked while still pinned
+    pub(crate) bin: LockedBin<'a, K, V>,
+    pub(crate) map: &'a ConcurrentHashMap<K, V, S>,
+    pub(crate) slot: Slot<K, V>,
+    pub(crate) guard: Guard<'a>,
+}
+
+/// A view into a vacant entry, holding the lock of its bin.
+pub struct VacantEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    // dropped first, so the bin is unlocked while still pinned
+    pub(crate) bin: LockedBin<'a, K, V>,
+    pub(crate) map: &'a ConcurrentHashMap<K, V, S>,
+    pub(crate) hash: usize,
+    // taken on insert or into_key
+    pub(crate) key: Option<K>,
+    pub(crate) tail: Tail<K, V>,
+    pub(crate) guard: Guard<'a>,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+    /// Ensures a value is in the entry by inserting `default` if empty.
+    /// Returns the value now in the entry.
+    pub fn or_insert(self, default: V) -> Value<'a, V> {
+        match self {
+            Entry::Occupied(e) => e.into_value(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+    /// Ensures a value is in the entry by inserting the result of `default` if empty.
+    /// The function is called while holding the bin lock.
+    /// Returns the value now in the entry.
+    pub fn or_insert_with<F>(self, default: F) -> Value<'a, V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_value(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+    /// Like `or_insert_with`, but the function is given the key.
+    pub fn or_insert_with_key<F>(self, default: F) -> Value<'a, V>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        match self {
+            Entry::Occupied(e) => e.into_value(),
+            Entry::Vacant(e) => {
+                let value = default(e.key());
+                e.insert(value)
+            }
+        }
+    }
+    /// Replaces the value of an occupied entry with the result of `f`, before any potential
+    /// insert. The current value may still be read by other threads, so unlike
+    /// `HashMap::entry` it is not modified in place but swapped for the returned one.
+    pub fn and_modify<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&V) -> V,
+    {
+        if let Entry::Occupied(e) = &mut self {
+            let value = f(e.get());
+            e.insert(value);
+        }
+        self
+    }
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Default + Send + 'static,
+    S: BuildHasher,
+{
+    /// Ensures a value is in the entry by inserting the default value if empty.
+    /// Returns the value now in the entry.
+    pub fn or_default(self) -> Value<'a, V> {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V, S> OccupiedEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    fn node(&self) -> *mut Node<K, V> {
+        match self.slot {
+            Slot::Linked { e, .. } => e,
+            Slot::Tree(p) => unsafe { (*p).node },
+        }
+    }
+    /// Returns a reference to the key in the entry.
+    pub fn key(&self) -> &K {
+        unsafe { &(*self.node()).key }
+    }
+    /// Returns a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        unsafe { &*(*self.node()).val.load(Ordering::Acquire) }
+    }
+    /// Sets the value of the entry.
+    /// Returns the old value, which is retired once the returned `Value` is dropped.
+    pub fn insert(&mut self, value: V) -> Value<'a, V> {
+        unsafe {
+            let node = self.node();
+            let old = (*node).val.load(Ordering::Acquire);
+            let value = Box::into_raw(Box::new(value));
+            (*node).val.store(value, Ordering::Release);
+            Value::new_drop(self.map.guard(), old)
+        }
+    }
+    /// Releases the bin lock and returns the value in the entry.
+    pub fn into_value(self) -> Value<'a, V> {
+        unsafe {
+            let val = (*self.node()).val.load(Ordering::Acquire);
+            Value::new(self.map.guard(), val)
+        }
+    }
+    /// Removes the entry from the map.
+    /// Returns the removed value, which is retired once the returned `Value` is dropped.
+    pub fn remove(self) -> Value<'a, V> {
+        unsafe {
+            let f = &self.bin.tab[self.bin.index];
+            let f_node_ptr = self.bin.node;
+            let old = (*self.node()).val.load(Ordering::Acquire);
+            match self.slot {
+                Slot::Linked { pred, e } => {
+                    ConcurrentHashMap::<K, V, S>::unlink_node(f, f_node_ptr, pred, e, &self.guard)
+                }
+                Slot::Tree(p) => {
+                    if let NodeEnums::TreeBin(t) = &mut *f_node_ptr {
+                        self.map.remove_tree_val(f, f_node_ptr, t, p, &self.guard);
+                    }
+                }
+            }
+            let map = self.map;
+            let guard = map.guard();
+            drop(self);
+            map.add_count(-1, -1, &guard);
+            Value::new_drop(guard, old)
+        }
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns a reference to the key that would be used when inserting through this entry.
+    pub fn key(&self) -> &K {
+        self.key.as_ref().unwrap()
+    }
+    /// Takes ownership of the key, leaving the map unchanged.
+    pub fn into_key(mut self) -> K {
+        self.key.take().unwrap()
+    }
+    /// Sets the value of the entry with its key, then releases the bin lock.
+    /// Returns the inserted value.
+    pub fn insert(mut self, value: V) -> Value<'a, V> {
+        unsafe {
+            let key = self.key.take().unwrap();
+            let val = Box::into_raw(Box::new(value));
+            let hash = self.hash;
+            let f = &self.bin.tab[self.bin.index];
+            let bin_count = match self.tail {
+                Tail::Reserved => {
+                    let node = NodeEnums::Node(Node::new(hash, key, val)).into_box();
+                    f.node.store(node, Ordering::Release);
+                    self.guard.defer_destroy(self.bin.node);
+                    self.bin.node = node;
+                    1
+                }
+                Tail::Linked { last, bin_count } => {
+                    (*last)
+                        .next
+                        .store(Node::new(hash, key, val).into_box(), Ordering::Release);
+                    bin_count
+                }
+                Tail::Tree => {
+                    if let NodeEnums::TreeBin(t) = &mut *self.bin.node {
+                        t.put_tree_val(hash, key, val, &self.guard);
+                    }
+                    2
+                }
+            };
+            let map = self.map;
+            let (tab, index) = (self.bin.tab, self.bin.index);
+            let guard = map.guard();
+            drop(self);
+            if bin_count >= map.treeify_threshold {
+                map.treeify_bin(tab, index, &guard);
+            }
+            map.add_count(1, bin_count as isize, &guard);
+            Value::new(guard, val)
+        }
+    }
+}
+
+impl<'a, K, V, S> Drop for VacantEntry<'a, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        // give back an empty bin that was reserved but never filled
+        unsafe {
+            if let NodeEnums::ReservationNode(_) = &*self.bin.node {
+                let f = &self.bin.tab[self.bin.index];
+                f.node.store(ptr::null_mut(), Ordering::Release);
+                self.guard.defer_destroy(self.bin.node);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::concurrent_hash_map::test_util::Ids;
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap, Entry};
+
+    /// Returns a map of 256 bins with a tree in bin 0 and a chain in bin 1.
+    fn filled() -> ConcurrentHashMap<u64, u64, Ids> {
+        let map = ConcurrentHashMap::with_capacity_and_hasher(100, Ids::default());
+        for id in (0..16).map(|i| i * 256).chain([1, 257, 513]) {
+            map.insert(id, id);
+        }
+        let stats = map.stats();
+        assert_eq!((stats.table_len, stats.tree_bins), (256, 1));
+        map
+    }
+
+    #[test]
+    fn entries_of_chain_and_tree_bins() {
+        let map = filled();
+        let mut size = map.size();
+        // an existing key at the head or inside the bin, then a missing one
+        for (present, absent) in [(0, 4096), (1024, 8192), (1, 769), (257, 1025)] {
+            assert_eq!(*map.entry(present).or_insert(7), present);
+            assert_eq!(map.size(), size);
+            assert_eq!(*map.entry(absent).or_insert(7), 7);
+            size += 1;
+            assert_eq!(map.size(), size);
+            let v = map.entry(present).and_modify(|v| v + 1).or_insert(7);
+            assert_eq!(*v, present + 1);
+            drop(v);
+            let missing = absent + 256 * 64;
+            assert_eq!(*map.entry(missing).and_modify(|v| v + 1).or_insert(7), 7);
+            size += 1;
+            assert_eq!(map.size(), size);
+            match map.entry(present) {
+                Entry::Occupied(e) => assert_eq!(*e.remove(), present + 1),
+                Entry::Vacant(_) => panic!("{present} is in the map"),
+            }
+            size -= 1;
+            assert_eq!(map.size(), size);
+            assert!(map.get(&present).is_none());
+            assert_eq!(map.get(&absent).as_deref(), Some(&7));
+        }
+        assert_eq!(map.stats().tree_bins, 1);
+    }
+
+    #[test]
+    fn removing_entries_untreeifies() {
+        let map = filled();
+        for i in 2..16 {
+            match map.entry(i * 256) {
+                Entry::Occupied(e) => drop(e.remove()),
+                Entry::Vacant(_) => panic!("{} is in the map", i * 256),
+            }
+        }
+        assert_eq!(map.size(), 5);
+        assert_eq!(map.stats().tree_bins, 0);
+        assert_eq!(map.get(&256).as_deref(), Some(&256));
+    }
+
+    #[test]
+    fn dropped_vacant_entries_release_their_bin() {
+        let map = filled();
+        // a reserved empty bin, a chain and a tree
+        for id in [2, 769, 4096] {
+            match map.entry(id) {
+                Entry::Vacant(e) => assert_eq!(*e.key(), id),
+                Entry::Occupied(_) => panic!("{id} is not in the map"),
+            }
+            assert!(map.get(&id).is_none());
+            thread::scope(|s| {
+                s.spawn(|| assert!(map.insert(id, id).is_none()));
+            });
+            assert_eq!(map.get(&id).as_deref(), Some(&id));
+        }
+        let Entry::Vacant(e) = map.entry(3) else {
+            panic!("3 is not in the map")
+        };
+        assert_eq!(e.into_key(), 3);
+        assert!(map.insert(3, 3).is_none());
+        assert_eq!(map.size(), 19 + 4);
+    }
+}
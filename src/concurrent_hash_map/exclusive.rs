@@ -0,0 +1,326 @@
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use std::{mem, ptr};
+
+use crate::concurrent_hash_map::base::{BaseNode, ConcurrentHashMap, NodeEnums};
+use crate::concurrent_hash_map::iter::Traverser;
+use crate::concurrent_hash_map::node::Node;
+use crate::ebr::collector::Unprotected;
+
+/// Operations on a map borrowed mutably or owned. No other thread can reach the map meanwhile,
+/// so they take no locks, do not pin the collector, and free removed entries right away.
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns a mutable reference to the value to which the specified key is mapped, or
+    /// `None` if the map contains no mapping for the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        unsafe { self.find(key, None).map(|v| &mut *v) }
+    }
+    /// Returns an iterator over the entries of this map, in arbitrary order, with mutable
+    /// references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        let tab = unsafe { self.table.get_mut().as_ref() }.map(|t| &**t);
+        let n = tab.map_or(0, |t| t.len());
+        IterMut {
+            it: Traverser::new(tab, n, 0, n),
+            marker: PhantomData,
+        }
+    }
+    /// Retains only the entries for which `f` returns true. `f` is called once for every
+    /// entry, with a mutable reference to its value.
+    pub fn retain_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let tab = match unsafe { self.table.load(Ordering::Relaxed).as_ref() } {
+            Some(tab) => tab,
+            None => return,
+        };
+        let mut removed = 0;
+        let mut keep = Vec::new();
+        for bin in tab.iter() {
+            removed += unsafe {
+                self.retain_bin(
+                    bin,
+                    bin.node.load(Ordering::Relaxed),
+                    |e| !f(&e.key, &mut *e.val.load(Ordering::Relaxed)),
+                    &mut keep,
+                    &Unprotected,
+                )
+            };
+        }
+        *self.base_count.get_mut() -= removed as isize;
+    }
+    /// Removes all entries from this map, returning them as an iterator. The map is empty
+    /// once the iterator is dropped, even if it was not fully consumed. Use `drain_cloned` to
+    /// drain a map that is shared.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        *self.base_count.get_mut() = 0;
+        if let Some(cells) = unsafe { self.counter_cells.get_mut().as_ref() } {
+            for cell in cells {
+                cell.store(0, Ordering::Relaxed);
+            }
+        }
+        Drain {
+            bins: Bins::new(*self.table.get_mut()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V, S> IntoIterator for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Returns an iterator moving the entries out of this map, in arbitrary order.
+    fn into_iter(mut self) -> IntoIter<K, V> {
+        IntoIter {
+            bins: Bins::new(mem::replace(self.table.get_mut(), ptr::null_mut())),
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a mut ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> IterMut<'a, K, V> {
+        self.iter_mut()
+    }
+}
+
+/// An iterator over the entries of a `ConcurrentHashMap` with mutable references to the
+/// values, created by `ConcurrentHashMap::iter_mut`.
+pub struct IterMut<'a, K, V> {
+    it: Traverser<'a, K, V>,
+    marker: PhantomData<&'a mut V>,
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.it.advance()?;
+        unsafe { Some((&*node.key, &mut *node.val.load(Ordering::Relaxed))) }
+    }
+}
+
+/// Moves the entries out of the bins of a table one at a time, freeing their nodes.
+struct Bins<K, V> {
+    tab: *mut Box<[BaseNode<K, V>]>,
+    // index of the next bin to empty
+    index: usize,
+    // the rest of the chain of the last emptied bin
+    next: *mut Node<K, V>,
+}
+
+impl<K, V> Bins<K, V> {
+    fn new(tab: *mut Box<[BaseNode<K, V>]>) -> Bins<K, V> {
+        Self {
+            tab,
+            index: 0,
+            next: ptr::null_mut(),
+        }
+    }
+    unsafe fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if !self.next.is_null() {
+                let node = *Box::from_raw(self.next);
+                self.next = node.next.load(Ordering::Relaxed);
+                return Some(Self::take(node));
+            }
+            let bin = self.tab.as_ref()?.get(self.index)?;
+            self.index += 1;
+            let p = bin.node.swap(ptr::null_mut(), Ordering::Relaxed);
+            if p.is_null() {
+                continue;
+            }
+            match *Box::from_raw(p) {
+                NodeEnums::Node(head) => {
+                    self.next = head.next.load(Ordering::Relaxed);
+                    return Some(Self::take(head));
+                }
+                // dropping the bin frees its tree, but not the nodes chained from first
+                NodeEnums::TreeBin(t) => self.next = t.first.load(Ordering::Relaxed),
+                NodeEnums::ForwardingNode(_) | NodeEnums::ReservationNode(_) => {}
+            }
+        }
+    }
+    unsafe fn take(node: Node<K, V>) -> (K, V) {
+        let val = *Box::from_raw(node.val.load(Ordering::Relaxed));
+        (node.into_key(), val)
+    }
+}
+
+/// A draining iterator over the entries of a `ConcurrentHashMap`, created by
+/// `ConcurrentHashMap::drain`.
+pub struct Drain<'a, K, V> {
+    bins: Bins<K, V>,
+    marker: PhantomData<&'a mut Box<[BaseNode<K, V>]>>,
+}
+
+unsafe impl<'a, K: Send, V: Send> Send for Drain<'a, K, V> {}
+
+impl<'a, K, V> Iterator for Drain<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        unsafe { self.bins.next() }
+    }
+}
+
+impl<'a, K, V> Drop for Drain<'a, K, V> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
+
+/// An owning iterator over the entries of a `ConcurrentHashMap`, created by `into_iter`.
+pub struct IntoIter<K, V> {
+    bins: Bins<K, V>,
+}
+
+unsafe impl<K: Send, V: Send> Send for IntoIter<K, V> {}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        unsafe { self.bins.next() }
+    }
+}
+
+impl<K, V> Drop for IntoIter<K, V> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+        if !self.bins.tab.is_null() {
+            unsafe { drop(Box::from_raw(self.bins.tab)) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    use crate::concurrent_hash_map::test_util::{Counted, Ids};
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap};
+
+    /// Fills a map of 256 bins with a tree in bin 0, chains in bins 1 to 3 and single nodes
+    /// in the others, counting live keys and values in `live`.
+    fn filled(live: &'static AtomicIsize) -> ConcurrentHashMap<Counted, Counted, Ids> {
+        let map = ConcurrentHashMap::with_capacity_and_hasher(100, Ids::default());
+        let ids = (0..16)
+            .map(|i| i * 256)
+            .chain((1..4).flat_map(|b| [b, b + 256, b + 512]));
+        for id in ids.chain(4..64) {
+            map.insert(Counted::new(id, live), Counted::new(id, live));
+        }
+        let stats = map.stats();
+        assert_eq!((stats.table_len, stats.tree_bins), (256, 1));
+        map
+    }
+
+    #[test]
+    fn get_mut_and_iter_mut_reach_chains_and_trees() {
+        static LIVE: AtomicIsize = AtomicIsize::new(0);
+        let mut map = filled(&LIVE);
+        let n = map.size();
+        for id in [0, 256, 3840, 2, 514, 5] {
+            map.get_mut(&id).unwrap().id += 1000;
+        }
+        assert!(map.get_mut(&4096).is_none());
+        let mut seen = 0;
+        for (k, v) in &mut map {
+            let bumped = [0, 256, 3840, 2, 514, 5].contains(&k.id);
+            assert_eq!(v.id, k.id + if bumped { 1000 } else { 0 });
+            v.id = k.id;
+            seen += 1;
+        }
+        assert_eq!(seen, n);
+        assert_eq!(map.get(&514).unwrap().id, 514);
+        drop(map);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn retain_mut_updates_the_size() {
+        static LIVE: AtomicIsize = AtomicIsize::new(0);
+        let mut map = filled(&LIVE);
+        let n = map.size();
+        let mut calls = 0;
+        map.retain_mut(|k, v| {
+            calls += 1;
+            v.id += 1;
+            k.id % 2 == 1
+        });
+        assert_eq!(calls, n);
+        assert_eq!(map.size(), 6 + 30);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2 * map.size() as isize);
+        assert_eq!(map.stats().tree_bins, 0);
+        assert!(map.get(&0).is_none());
+        assert_eq!(map.get(&257).unwrap().id, 258);
+        map.retain_mut(|_, _| false);
+        assert_eq!(map.size(), 0);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn drain_dropped_part_way_empties_the_map() {
+        static LIVE: AtomicIsize = AtomicIsize::new(0);
+        let mut map = filled(&LIVE);
+        let n = map.size();
+        let taken: Vec<_> = map.drain().take(20).collect();
+        assert_eq!(map.size(), 0);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2 * taken.len() as isize);
+        assert!(map.iter_mut().next().is_none());
+        drop(taken);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+        // the drained map stays usable, and a full drain yields every entry
+        for id in 0..n as u64 {
+            map.insert(Counted::new(id, &LIVE), Counted::new(id, &LIVE));
+        }
+        assert_eq!(map.size(), n);
+        let mut ids: Vec<_> = map.drain().map(|(k, v)| (k.id, v.id)).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..n as u64).map(|id| (id, id)).collect::<Vec<_>>());
+        assert_eq!(map.size(), 0);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn into_iter_dropped_part_way_frees_the_rest() {
+        static LIVE: AtomicIsize = AtomicIsize::new(0);
+        let map = filled(&LIVE);
+        let n = map.size();
+        let mut it = map.into_iter();
+        let (k, v) = it.next().unwrap();
+        assert_eq!(k.id, v.id);
+        let rest: Vec<_> = it.by_ref().take(20).collect();
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2 * n as isize);
+        drop(it);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 2 * (rest.len() + 1) as isize);
+        drop((k, v, rest));
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+}
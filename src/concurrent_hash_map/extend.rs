@@ -0,0 +1,209 @@
+use std::hash::{BuildHasher, Hash};
+
+use crate::concurrent_hash_map::base::{batch_for_size, ConcurrentHashMap};
+use crate::concurrent_hash_map::bulk::Task;
+use crate::concurrent_hash_map::map::ConcurrentMap;
+
+/// Bulk insertion, in the manner of the JDK's putAll. The table is resized once for the
+/// expected number of entries before inserting them, instead of doubling step by step.
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Copies all of the mappings from `other` to this map, replacing the values of keys
+    /// already present. `other` is read like by `iter`, so concurrent updates to it may or
+    /// may not be copied.
+    pub fn put_all<T>(&self, other: &ConcurrentHashMap<K, V, T>)
+    where
+        K: Clone,
+        V: Clone,
+        T: BuildHasher,
+    {
+        let guard = other.guard();
+        let entries = other.iter(&guard).map(|(k, v)| (k.clone(), v.clone()));
+        self.put_entries(other.size(), entries);
+    }
+    /// Inserts the given entries like `extend`, splitting them into batches run by the
+    /// executor of this map. `parallelism_threshold` is the number of entries needed to
+    /// insert in parallel, as for the bulk operations. The entries are collected first, and
+    /// split by hash, so that equal keys land in the same batch and the last one wins.
+    pub fn extend_parallel<I>(&self, parallelism_threshold: usize, entries: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+        S: Sync,
+    {
+        let entries: Vec<(K, V)> = entries.into_iter().collect();
+        let n = entries.len();
+        let batches = batch_for_size(n, parallelism_threshold);
+        if batches <= 1 {
+            return self.put_entries(n, entries);
+        }
+        let guard = self.guard();
+        unsafe { self.try_presize(n, &guard) };
+        drop(guard);
+        let mut split: Vec<Vec<(K, V)>> = (0..batches)
+            .map(|_| Vec::with_capacity(n.div_ceil(batches)))
+            .collect();
+        for (key, value) in entries {
+            split[self.spread(&key) % batches].push((key, value));
+        }
+        let tasks: Vec<Task<'_>> = split
+            .into_iter()
+            .map(|batch| -> Task<'_> { Box::new(move || self.put_entries(0, batch)) })
+            .collect();
+        self.executor().execute(tasks);
+    }
+    /// Inserts `entries`, after presizing the table for `size` elements unless it is 0.
+    fn put_entries<I>(&self, size: usize, entries: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let guard = self.guard();
+        unsafe {
+            if size > 0 {
+                self.try_presize(size, &guard);
+            }
+            for (key, value) in entries {
+                match self.put_val(key, value, false, &guard) {
+                    Ok(Some(old)) => guard.defer_destroy(old),
+                    Ok(None) => {}
+                    Err(_) => unreachable!("only rejected if absent was requested"),
+                }
+            }
+        }
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for &ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Inserts the entries of `iter`, presizing the table for the lower bound of its
+    /// `size_hint`.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.put_entries(iter.size_hint().0, iter);
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        (&*self).extend(iter)
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::hash::BuildHasher;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::concurrent_hash_map::test_util::Ids;
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap, Executor, Task};
+
+    /// Runs the tasks one after another, in order or last first, counting them.
+    #[derive(Clone, Default)]
+    struct Sequential {
+        reverse: bool,
+        tasks: Arc<AtomicUsize>,
+    }
+
+    impl Executor for Sequential {
+        fn execute<'s>(&self, mut tasks: Vec<Task<'s>>) {
+            self.tasks.fetch_add(tasks.len(), Ordering::Relaxed);
+            if self.reverse {
+                tasks.reverse();
+            }
+            tasks.into_iter().for_each(|task| task());
+        }
+    }
+
+    fn contents<S: BuildHasher>(map: &ConcurrentHashMap<u64, u64, S>) -> BTreeMap<u64, u64> {
+        let guard = map.guard();
+        let entries = map.iter(&guard).map(|(k, v)| (*k, *v)).collect();
+        entries
+    }
+
+    /// Entries for 100 keys, each repeated 50 times, with the last value of key `k` being
+    /// `4900 + k`.
+    fn duplicates() -> impl Iterator<Item = (u64, u64)> {
+        (0..5000).map(|i| (i % 100, i))
+    }
+
+    fn last_values() -> BTreeMap<u64, u64> {
+        (0..100).map(|k| (k, 4900 + k)).collect()
+    }
+
+    #[test]
+    fn extend_from_iter_and_put_all_keep_the_last_duplicate() {
+        let map: ConcurrentHashMap<u64, u64> = duplicates().collect();
+        assert_eq!(contents(&map), last_values());
+        assert_eq!(map.size(), 100);
+        let mut other = ConcurrentHashMap::with_hasher(Ids::default());
+        other.extend((0..200).map(|k| (k, 0)));
+        other.extend(duplicates());
+        (&other).extend([(150, 1), (150, 2)]);
+        let mut expected = last_values();
+        expected.extend((100..200).map(|k| (k, 0)));
+        expected.insert(150, 2);
+        assert_eq!(contents(&other), expected);
+        other.put_all(&map);
+        assert_eq!(other.size(), 200);
+        assert_eq!(contents(&other), expected);
+    }
+
+    #[test]
+    fn extend_parallel_keeps_the_last_duplicate() {
+        for reverse in [false, true] {
+            let mut map = ConcurrentHashMap::new();
+            let executor = Sequential {
+                reverse,
+                ..Sequential::default()
+            };
+            map.set_executor(executor.clone());
+            map.extend_parallel(1, duplicates());
+            assert!(executor.tasks.load(Ordering::Relaxed) > 1);
+            assert_eq!(contents(&map), last_values());
+            assert_eq!(map.size(), 100);
+        }
+    }
+
+    #[test]
+    fn extend_parallel_with_few_entries_runs_inline() {
+        let mut map = ConcurrentHashMap::with_hasher(Ids::default());
+        let executor = Sequential::default();
+        map.set_executor(executor.clone());
+        map.extend_parallel(1, []);
+        assert!(map.is_empty());
+        // fewer entries than the threshold for a single batch
+        map.extend_parallel(100, (0..10).map(|k| (k, k)).chain([(3, 30)]));
+        assert_eq!(executor.tasks.load(Ordering::Relaxed), 0);
+        let mut expected: BTreeMap<_, _> = (0..10).map(|k| (k, k)).collect();
+        expected.insert(3, 30);
+        assert_eq!(contents(&map), expected);
+        assert_eq!(map.size(), 10);
+    }
+}
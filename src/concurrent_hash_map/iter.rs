@@ -0,0 +1,202 @@
+use std::sync::atomic::Ordering;
+
+use crate::concurrent_hash_map::base::{BaseNode, NodeEnums};
+use crate::concurrent_hash_map::node::Node;
+
+/// Records the table, its length, and current traversal index for a traverser that must
+/// process a region of a forwarded table before proceeding with the current table.
+struct TableStack<'g, K, V> {
+    tab: &'g [BaseNode<K, V>],
+    length: usize,
+    index: usize,
+    filter: Option<(usize, usize)>,
+}
+
+/// Encapsulates traversal for methods such as iter and the bulk operations.
+///
+/// Each Traverser processes the bins of a contiguous range of the initial table. Because the
+/// table may be resized while it is being traversed, on reaching a `ForwardingNode` the
+/// traverser descends into the next table, visits the two bins the current bin was split into
+/// (index and index + length of the smaller table), and then resumes with the smaller one.
+/// If the next table is smaller, as after `shrink_to_fit`, the bin it descends into also holds
+/// the nodes of another bin of the current table, and those are skipped by hash.
+/// This way no key is skipped or yielded twice, although updates made after the traversal
+/// started may or may not be seen. All tables and nodes it hands out are kept alive by the
+/// guard the caller pinned for lifetime `'g`.
+pub(crate) struct Traverser<'g, K, V> {
+    // current table; updated if resized
+    tab: Option<&'g [BaseNode<K, V>]>,
+    // the next entry to use
+    next: Option<&'g Node<K, V>>,
+    // to save/restore on ForwardingNodes
+    stack: Vec<TableStack<'g, K, V>>,
+    // index of bin to use next
+    index: usize,
+    // current index of initial table
+    base_index: usize,
+    // index bound for initial table
+    base_limit: usize,
+    // initial table size
+    base_size: usize,
+    // (mask, index) of the hashes to visit while in a merged bin, if any
+    filter: Option<(usize, usize)>,
+    // the filter that was in effect when the bin of `next` was read
+    next_filter: Option<(usize, usize)>,
+}
+
+impl<'g, K, V> Traverser<'g, K, V> {
+    pub(crate) fn new(
+        tab: Option<&'g [BaseNode<K, V>]>,
+        size: usize,
+        index: usize,
+        limit: usize,
+    ) -> Traverser<'g, K, V> {
+        Self {
+            tab,
+            next: None,
+            stack: Vec::new(),
+            index,
+            base_index: index,
+            base_limit: limit,
+            base_size: size,
+            filter: None,
+            next_filter: None,
+        }
+    }
+    /// Advances if possible, returning next valid node, or `None` if none.
+    pub(crate) fn advance(&mut self) -> Option<&'g Node<K, V>> {
+        let mut e = self
+            .next
+            .and_then(|e| unsafe { e.next.load(Ordering::Acquire).as_ref() });
+        loop {
+            if let Some(node) = e {
+                match self.next_filter {
+                    Some((mask, index)) if node.hash & mask != index => {
+                        e = unsafe { node.next.load(Ordering::Acquire).as_ref() };
+                        continue;
+                    }
+                    _ => {
+                        self.next = e;
+                        return e;
+                    }
+                }
+            }
+            let i = self.index;
+            let t = match self.tab {
+                Some(t) if self.base_index < self.base_limit && i < t.len() => t,
+                _ => {
+                    self.next = None;
+                    return None;
+                }
+            };
+            let n = t.len();
+            unsafe {
+                match t[i].node.load(Ordering::Acquire).as_ref() {
+                    Some(NodeEnums::ForwardingNode(f)) => {
+                        let next_tab = &**f.next_table;
+                        self.push_state(t, i, n);
+                        if next_tab.len() < n {
+                            // merged with other bins, keep the narrowest filter
+                            if self.filter.is_none_or(|(mask, _)| mask < n - 1) {
+                                self.filter = Some((n - 1, i));
+                            }
+                            self.index = i & (next_tab.len() - 1);
+                        }
+                        self.tab = Some(next_tab);
+                        continue;
+                    }
+                    Some(NodeEnums::TreeBin(b)) => e = b.first.load(Ordering::Acquire).as_ref(),
+                    Some(NodeEnums::Node(node)) => e = Some(node),
+                    Some(NodeEnums::ReservationNode(_)) | None => {}
+                }
+            }
+            self.next_filter = self.filter;
+            if !self.stack.is_empty() {
+                self.recover_state(n);
+            } else {
+                self.index = i + self.base_size;
+                if self.index >= n {
+                    // visit upper slots if present
+                    self.base_index += 1;
+                    self.index = self.base_index;
+                }
+            }
+        }
+    }
+    /// Saves traversal state upon encountering a forwarding node.
+    fn push_state(&mut self, tab: &'g [BaseNode<K, V>], index: usize, length: usize) {
+        self.stack.push(TableStack {
+            tab,
+            length,
+            index,
+            filter: self.filter,
+        });
+    }
+    /// Possibly pops traversal state.
+    fn recover_state(&mut self, mut n: usize) {
+        while let Some(s) = self.stack.last() {
+            self.index += s.length;
+            if self.index < n {
+                return;
+            }
+            n = s.length;
+            self.index = s.index;
+            self.tab = Some(s.tab);
+            self.filter = s.filter;
+            self.stack.pop();
+        }
+        self.index += self.base_size;
+        if self.index >= n {
+            self.base_index += 1;
+            self.index = self.base_index;
+        }
+    }
+}
+
+/// An iterator over the entries of a `ConcurrentHashMap`.
+///
+/// The iterator is weakly consistent: it never yields a key twice and reflects the state of
+/// the map at some point at or since its creation. The references it yields stay valid for as
+/// long as the guard passed to `ConcurrentHashMap::iter` is pinned.
+pub struct Iter<'g, K, V> {
+    pub(crate) it: Traverser<'g, K, V>,
+}
+
+impl<'g, K, V> Iterator for Iter<'g, K, V> {
+    type Item = (&'g K, &'g V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.it.advance()?;
+        unsafe { Some((&*node.key, &*node.val.load(Ordering::Acquire))) }
+    }
+}
+
+/// An iterator over the keys of a `ConcurrentHashMap`. See `Iter` for its consistency
+/// guarantees.
+pub struct Keys<'g, K, V> {
+    pub(crate) it: Traverser<'g, K, V>,
+}
+
+impl<'g, K, V> Iterator for Keys<'g, K, V> {
+    type Item = &'g K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.it.advance()?;
+        Some(&*node.key)
+    }
+}
+
+/// An iterator over the values of a `ConcurrentHashMap`. See `Iter` for its consistency
+/// guarantees.
+pub struct Values<'g, K, V> {
+    pub(crate) it: Traverser<'g, K, V>,
+}
+
+impl<'g, K, V> Iterator for Values<'g, K, V> {
+    type Item = &'g V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.it.advance()?;
+        unsafe { Some(&*node.val.load(Ordering::Acquire)) }
+    }
+}
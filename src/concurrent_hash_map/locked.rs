@@ -0,0 +1,207 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::concurrent_hash_map::map::ConcurrentMap;
+
+/// A `ConcurrentMap` that guards a `HashMap` with a single read-write lock. It is far slower
+/// than `ConcurrentHashMap` under contention, but simple enough to be obviously correct, which
+/// makes it a reference to test other implementations against.
+///
+/// Values are stored behind an `Arc` and handed out as clones of it, so no lock is held by
+/// the values returned. The functions passed to the compute methods run under the write lock.
+pub struct LockedHashMap<K, V, S = RandomState> {
+    map: RwLock<HashMap<K, Arc<V>, S>>,
+}
+
+impl<K, V> LockedHashMap<K, V, RandomState>
+where
+    K: Hash + Eq,
+{
+    pub fn new() -> LockedHashMap<K, V> {
+        Self::with_hasher(RandomState::new())
+    }
+    /// Creates an empty map with room for `capacity` entries without reallocating.
+    pub fn with_capacity(capacity: usize) -> LockedHashMap<K, V> {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K, V, S> Default for LockedHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> LockedHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self {
+            map: RwLock::new(HashMap::with_hasher(hash_builder)),
+        }
+    }
+    /// Creates an empty map which will use `hash_builder` to hash keys, with room for
+    /// `capacity` entries without reallocating.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: RwLock::new(HashMap::with_capacity_and_hasher(capacity, hash_builder)),
+        }
+    }
+}
+
+impl<K, V, S> ConcurrentMap<K, V> for LockedHashMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    type Ref<'a>
+        = Arc<V>
+    where
+        Self: 'a;
+
+    fn size(&self) -> usize {
+        self.map.read().len()
+    }
+    fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        self.map.read().values().any(|v| **v == *value)
+    }
+    fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.read().get(key).cloned()
+    }
+    fn insert(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.map.write().insert(key, Arc::new(value))
+    }
+    fn put_if_absent(&self, key: K, value: V) -> Option<Arc<V>> {
+        match self.map.write().entry(key) {
+            Entry::Occupied(e) => Some(e.get().clone()),
+            Entry::Vacant(e) => {
+                e.insert(Arc::new(value));
+                None
+            }
+        }
+    }
+    fn remove<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.write().remove(key)
+    }
+    fn remove_entry<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        let mut map = self.map.write();
+        match map.get(key) {
+            Some(v) if **v == *value => map.remove(key).is_some(),
+            _ => false,
+        }
+    }
+    fn replace<Q>(&self, key: &Q, old_value: &V, new_value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        match self.map.write().get_mut(key) {
+            Some(v) if **v == *old_value => {
+                *v = Arc::new(new_value);
+                true
+            }
+            _ => false,
+        }
+    }
+    fn compute_if_absent<F>(&self, key: K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&K) -> Option<V>,
+    {
+        match self.map.write().entry(key) {
+            Entry::Occupied(e) => Some(e.get().clone()),
+            Entry::Vacant(e) => {
+                let value = Arc::new(f(e.key())?);
+                Some(e.insert(value).clone())
+            }
+        }
+    }
+    fn compute_if_present<F>(&self, key: &K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&K, &V) -> Option<V>,
+    {
+        let mut map = self.map.write();
+        let (k, v) = map.get_key_value(key)?;
+        match f(k, v) {
+            Some(value) => {
+                let value = Arc::new(value);
+                *map.get_mut(key)? = value.clone();
+                Some(value)
+            }
+            None => {
+                map.remove(key);
+                None
+            }
+        }
+    }
+    fn compute<F>(&self, key: K, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&K, Option<&V>) -> Option<V>,
+    {
+        match self.map.write().entry(key) {
+            Entry::Occupied(mut e) => match f(e.key(), Some(e.get())) {
+                Some(value) => {
+                    let value = Arc::new(value);
+                    e.insert(value.clone());
+                    Some(value)
+                }
+                None => {
+                    e.remove();
+                    None
+                }
+            },
+            Entry::Vacant(e) => {
+                let value = Arc::new(f(e.key(), None)?);
+                Some(e.insert(value).clone())
+            }
+        }
+    }
+    fn merge<F>(&self, key: K, value: V, f: F) -> Option<Arc<V>>
+    where
+        F: FnOnce(&V, V) -> Option<V>,
+    {
+        self.compute(key, |_, old| match old {
+            None => Some(value),
+            Some(old) => f(old, value),
+        })
+    }
+    fn clear(&self) {
+        self.map.write().clear();
+    }
+    fn for_each_entry<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &V),
+    {
+        for (k, v) in self.map.read().iter() {
+            f(k, v);
+        }
+    }
+}
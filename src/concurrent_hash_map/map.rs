@@ -1,16 +1,103 @@
 use crate::ebr::collector::Guard;
+use std::borrow::Borrow;
+use std::hash::Hash;
 use std::ops::Deref;
 
-pub trait Map<K, V> {
+/// A map that can be shared between threads, with the operations of the JDK's
+/// `ConcurrentMap`. Implemented by `ConcurrentHashMap` and `ArcValueMap`, and by
+/// `LockedHashMap` as a simple reference to compare them against.
+///
+/// Values are handed out as `Ref`s, which keep them alive after they are replaced or
+/// removed. The functions passed to the compute methods run atomically with respect to other
+/// updates of the same key, so they should be short, and must not update the map.
+///
+/// There is no iterator method. The entries of a map can only be borrowed while it is pinned
+/// or locked, which an iterator borrowing just the map could not express for its items, so
+/// iteration is the callback of `for_each_entry`. Code that needs an iterator can use the
+/// `iter` of the backend itself, for example `ConcurrentHashMap::iter` with a guard.
+pub trait ConcurrentMap<K, V> {
+    /// A value read from the map, or replaced or removed from it.
+    type Ref<'a>: Deref<Target = V>
+    where
+        Self: 'a;
+
+    /// Returns the number of mappings.
     fn size(&self) -> usize;
-    // fn is_empty(&self) ->bool;
-    // fn contains_key(&self,key:K)->bool;
-    // fn contains_value(&self,value:V)->bool;
-    fn get(&self, key: &K) -> Option<Value<V>>;
-    fn insert(&self, key: K, value: V) -> Option<Value<V>>;
-    // fn remove(&self,key:&K)->;
-    // fn clear(&self);
+    fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(key).is_some()
+    }
+    /// Returns true if some key is mapped to `value`. Walks the whole map.
+    fn contains_value(&self, value: &V) -> bool
+    where
+        V: PartialEq;
+    fn get<Q>(&self, key: &Q) -> Option<Self::Ref<'_>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+    /// Maps `key` to `value`, returning the previous value.
+    fn insert(&self, key: K, value: V) -> Option<Self::Ref<'_>>;
+    /// Maps `key` to `value` unless it is present, returning the present value.
+    fn put_if_absent(&self, key: K, value: V) -> Option<Self::Ref<'_>>;
+    /// Removes the mapping for `key`, returning its value.
+    fn remove<Q>(&self, key: &Q) -> Option<Self::Ref<'_>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+    /// Removes the mapping for `key` only if it is mapped to `value`.
+    /// Returns true if it was removed.
+    fn remove_entry<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq;
+    /// Replaces the value of `key` only if it is mapped to `old_value`.
+    /// Returns true if it was replaced.
+    fn replace<Q>(&self, key: &Q, old_value: &V, new_value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq;
+    /// Maps an absent `key` to the value computed by `f`, unless it returns `None`.
+    /// `f` runs atomically with the update, and must not access this map: it may deadlock,
+    /// or panic with "Recursive update" for a `ConcurrentHashMap`. The same goes for the `f`
+    /// of the other compute methods.
+    /// Returns the present or computed value.
+    fn compute_if_absent<F>(&self, key: K, f: F) -> Option<Self::Ref<'_>>
+    where
+        F: FnOnce(&K) -> Option<V>;
+    /// Remaps a present `key` to the value computed by `f`, or removes it if `f` returns
+    /// `None`. Returns the new value.
+    fn compute_if_present<F>(&self, key: &K, f: F) -> Option<Self::Ref<'_>>
+    where
+        F: FnOnce(&K, &V) -> Option<V>;
+    /// Maps `key` to the value computed by `f` from the current one, or removes it if `f`
+    /// returns `None`. Returns the new value.
+    fn compute<F>(&self, key: K, f: F) -> Option<Self::Ref<'_>>
+    where
+        F: FnOnce(&K, Option<&V>) -> Option<V>;
+    /// Maps an absent `key` to `value`, or remaps a present one to the value `f` computes
+    /// from the current value and `value`, removing it if `f` returns `None`.
+    /// Returns the new value.
+    fn merge<F>(&self, key: K, value: V, f: F) -> Option<Self::Ref<'_>>
+    where
+        F: FnOnce(&V, V) -> Option<V>;
+    fn clear(&self);
+    /// Calls `f` on every mapping, in arbitrary order. Like iterating a `ConcurrentHashMap`,
+    /// this reflects the map at some point at or since the call, and `f` must not update the
+    /// map.
+    fn for_each_entry<F>(&self, f: F)
+    where
+        F: FnMut(&K, &V);
 }
+/// A value read from a `ConcurrentHashMap`, or replaced or removed from it. The collector
+/// stays pinned while it is held, so that the value is not freed meanwhile.
 pub struct Value<'a, V> {
     guard: Guard<'a>,
     val: *mut V,
@@ -35,7 +122,7 @@ impl<'a, V> Value<'a, V> {
 impl<'a, V> Drop for Value<'a, V> {
     fn drop(&mut self) {
         if self.is_drop {
-            self.guard.defer_destroy(self.val);
+            unsafe { self.guard.defer_destroy(self.val) };
         }
     }
 }
@@ -46,3 +133,133 @@ impl<'a, V> Deref for Value<'a, V> {
         unsafe { &*self.val }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::collections::BTreeMap;
+    use std::hash::BuildHasher;
+    use std::thread;
+
+    use super::*;
+    use crate::concurrent_hash_map::test_util::FewIds;
+    use crate::concurrent_hash_map::{ArcValueMap, ConcurrentHashMap, LockedHashMap};
+
+    fn entries<M: ConcurrentMap<u64, u64>>(map: &M) -> BTreeMap<u64, u64> {
+        let mut entries = BTreeMap::new();
+        map.for_each_entry(|&k, &v| assert!(entries.insert(k, v).is_none()));
+        entries
+    }
+
+    /// Runs a sequence of operations given by `seed` on `map`, returning their results.
+    fn run<M: ConcurrentMap<u64, u64>>(map: &M, seed: u64, keys: u64) -> Vec<Option<u64>> {
+        let mut x = seed;
+        let mut results = Vec::new();
+        for _ in 0..20_000 {
+            // xorshift
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let k = x % keys;
+            let v = (x >> 20) % 8;
+            let r = match (x >> 40) % 14 {
+                0 => map.insert(k, v).map(|r| *r),
+                1 => map.put_if_absent(k, v).map(|r| *r),
+                2 => map.remove(&k).map(|r| *r),
+                3 => Some(map.remove_entry(&k, &v) as u64),
+                4 => Some(map.replace(&k, &v, v + 1) as u64),
+                5 => map
+                    .compute_if_absent(k, |k| (!k.is_multiple_of(3)).then_some(*k))
+                    .map(|r| *r),
+                6 => map
+                    .compute_if_present(&k, |_, &v| (v <= 5).then_some(v + 2))
+                    .map(|r| *r),
+                7 => map
+                    .compute(k, |_, v| match v {
+                        None => Some(1),
+                        Some(&v) => (v <= 6).then_some(v * 2),
+                    })
+                    .map(|r| *r),
+                8 => map
+                    .merge(k, v, |&old, v| (old != v).then_some(old + v))
+                    .map(|r| *r),
+                9 => map.get(&k).map(|r| *r),
+                10 => Some(map.contains_key(&k) as u64),
+                11 => Some(map.contains_value(&v) as u64),
+                12 => Some(map.is_empty() as u64),
+                _ => {
+                    if x.is_multiple_of(97) {
+                        map.clear();
+                    }
+                    Some(map.size() as u64)
+                }
+            };
+            results.push(r);
+        }
+        results
+    }
+
+    fn differential<S>(keys: u64)
+    where
+        S: BuildHasher + Default + Send + Sync + 'static,
+    {
+        for seed in [1, 42, 0xdead_beef] {
+            let reference = LockedHashMap::<u64, u64, S>::with_hasher(S::default());
+            let expected = run(&reference, seed, keys);
+            let map = ConcurrentHashMap::<u64, u64, S>::with_hasher(S::default());
+            assert_eq!(run(&map, seed, keys), expected, "seed {}", seed);
+            assert_eq!(entries(&map), entries(&reference));
+            let map = ArcValueMap::<u64, u64, S>::with_hasher(S::default());
+            assert_eq!(run(&map, seed, keys), expected, "seed {}", seed);
+            assert_eq!(entries(&map), entries(&reference));
+        }
+    }
+
+    #[test]
+    fn backends_agree() {
+        differential::<RandomState>(512);
+    }
+
+    #[test]
+    fn backends_agree_on_tree_bins() {
+        // 4 hashes, so that the bins of a `ConcurrentHashMap` grow into trees
+        differential::<FewIds<4>>(128);
+    }
+
+    fn merge_concurrently<M: ConcurrentMap<u64, u64> + Sync>(map: M) {
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for i in 0..2_000 {
+                        map.merge(i % 100, 1, |old, v| Some(old + v));
+                    }
+                });
+            }
+        });
+        let entries = entries(&map);
+        assert_eq!(entries.len(), 100);
+        assert!(entries.values().all(|&v| v == 80));
+    }
+
+    #[test]
+    fn backends_merge_atomically() {
+        merge_concurrently(ConcurrentHashMap::new());
+        merge_concurrently(ArcValueMap::new());
+        merge_concurrently(LockedHashMap::new());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn former_name_of_the_trait() {
+        use crate::concurrent_hash_map::Map;
+
+        fn insert<M: Map<u64, u64>>(map: &M) -> Option<u64> {
+            map.insert(1, 1);
+            map.insert(1, 2).map(|v| *v)
+        }
+        let map = ConcurrentHashMap::new();
+        assert_eq!(insert(&map), Some(1));
+        let v: Value<'_, u64> = map.get(&1).unwrap();
+        assert_eq!(*v, 2);
+    }
+}
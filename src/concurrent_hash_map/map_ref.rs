@@ -0,0 +1,183 @@
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+
+use crate::concurrent_hash_map::base::ConcurrentHashMap;
+use crate::concurrent_hash_map::iter::{Iter, Keys, Values};
+use crate::concurrent_hash_map::map::ConcurrentMap;
+use crate::ebr::collector::Guard;
+
+/// A handle to a map holding a single pinned guard, created by `ConcurrentHashMap::pin`.
+///
+/// Operations through it skip pinning the collector each time, and return plain references
+/// living as long as the handle instead of `Value`s. Replaced and removed values are retired
+/// right away, but they are not reclaimed before the handle is dropped. Since nothing read
+/// through the handle can be reclaimed while it is alive, keep it short lived.
+pub struct MapRef<'m, K, V, S> {
+    map: &'m ConcurrentHashMap<K, V, S>,
+    guard: Guard<'m>,
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Pins the collector of this map once for a batch of operations.
+    pub fn pin(&self) -> MapRef<'_, K, V, S> {
+        MapRef {
+            map: self,
+            guard: self.guard(),
+        }
+    }
+}
+
+impl<'m, K, V, S> MapRef<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns the map this handle refers to.
+    pub fn map(&self) -> &'m ConcurrentHashMap<K, V, S> {
+        self.map
+    }
+    /// Returns the guard held by this handle.
+    pub fn guard(&self) -> &Guard<'m> {
+        &self.guard
+    }
+    /// Returns the number of mappings in the map.
+    pub fn len(&self) -> usize {
+        self.map.size()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the value to which the specified key is mapped, or `None` if the map contains
+    /// no mapping for the key.
+    pub fn get<'g, Q>(&'g self, key: &Q) -> Option<&'g V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        unsafe { self.map.find(key, None).map(|v| &*v) }
+    }
+    /// Returns true if the map contains a mapping for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(key).is_some()
+    }
+    /// Maps the specified key to the specified value.
+    /// Returns the previous value, or `None` if there was no mapping for the key.
+    pub fn insert(&self, key: K, value: V) -> Option<&V> {
+        unsafe {
+            match self.map.put_val(key, value, false, &self.guard) {
+                Ok(old) => old.map(|old| {
+                    self.guard.defer_destroy(old);
+                    &*old
+                }),
+                Err(_) => unreachable!("only rejected if absent was requested"),
+            }
+        }
+    }
+    /// Removes the key (and its corresponding value) from the map.
+    /// Returns the previous value, or `None` if there was no mapping for the key.
+    pub fn remove<'g, Q>(&'g self, key: &Q) -> Option<&'g V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        unsafe {
+            self.map
+                .replace_node(key, None, None, |_| true, &self.guard)
+                .map(|old| {
+                    self.guard.defer_destroy(old);
+                    &*old
+                })
+        }
+    }
+    /// Returns an iterator over the entries of the map, see `ConcurrentHashMap::iter`.
+    pub fn iter<'g>(&'g self) -> Iter<'g, K, V> {
+        self.map.iter(&self.guard)
+    }
+    /// Returns an iterator over the keys of the map, see `ConcurrentHashMap::iter`.
+    pub fn keys<'g>(&'g self) -> Keys<'g, K, V> {
+        self.map.keys(&self.guard)
+    }
+    /// Returns an iterator over the values of the map, see `ConcurrentHashMap::iter`.
+    pub fn values<'g>(&'g self) -> Values<'g, K, V> {
+        self.map.values(&self.guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicIsize, Ordering};
+    use std::thread;
+
+    use crate::concurrent_hash_map::test_util::{Counted, Ids};
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap};
+
+    #[test]
+    fn get_insert_remove_and_iter() {
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        let pinned = map.pin();
+        assert!(pinned.is_empty());
+        for i in 0..100u64 {
+            assert!(pinned.insert(i, i).is_none());
+        }
+        assert_eq!(pinned.insert(7, 70), Some(&7));
+        assert_eq!(pinned.get(&7), Some(&70));
+        assert_eq!(pinned.remove(&8), Some(&8));
+        assert_eq!(pinned.remove(&8), None);
+        assert!(pinned.get(&8).is_none() && !pinned.contains_key(&8));
+        assert_eq!(pinned.len(), 99);
+        let mut keys: Vec<_> = pinned.keys().copied().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, (0..100).filter(|&i| i != 8).collect::<Vec<_>>());
+        assert_eq!(pinned.values().sum::<u64>(), (0..100).sum::<u64>() - 8 + 63);
+        assert!(pinned
+            .iter()
+            .all(|(k, v)| *v == if *k == 7 { 70 } else { *k }));
+    }
+
+    #[test]
+    fn references_outlive_removal_and_replacement() {
+        static LIVE: AtomicIsize = AtomicIsize::new(0);
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        for i in 0..64 {
+            map.insert(i, Counted::new(i, &LIVE));
+        }
+        let pinned = map.pin();
+        let values: Vec<&Counted> = (0..64).map(|i| pinned.get(&i).unwrap()).collect();
+        let removed = pinned.remove(&0).unwrap();
+        let replaced = pinned.insert(1, Counted::new(100, &LIVE)).unwrap();
+        // other threads remove and replace the rest, and resize the table meanwhile
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in (2..64).step_by(2) {
+                    map.remove(&i);
+                }
+            });
+            s.spawn(|| {
+                for i in (3..64).step_by(2) {
+                    map.insert(i, Counted::new(i + 100, &LIVE));
+                }
+                for i in 64..1024 {
+                    map.insert(i, Counted::new(i, &LIVE));
+                }
+            });
+        });
+        assert_eq!((removed.id, replaced.id), (0, 1));
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(v.id, i as u64);
+        }
+        assert_eq!(LIVE.load(Ordering::Relaxed), 64 + 1 + 31 + 960);
+        drop(pinned);
+        drop(map);
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+}
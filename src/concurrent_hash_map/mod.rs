@@ -1,7 +1,43 @@
+mod arc_value;
 mod base;
+mod builder;
+mod bulk;
+mod entry;
+mod exclusive;
+mod extend;
 pub(crate) mod forwarding;
+mod iter;
+mod locked;
 mod map;
+mod map_ref;
 pub(crate) mod node;
+#[cfg(feature = "rayon")]
+mod rayon_impl;
+pub(crate) mod reservation;
+mod retain;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod set;
+mod stats;
+#[cfg(test)]
+pub(crate) mod test_util;
 pub(crate) mod tree;
+mod view;
+pub use arc_value::ArcValueMap;
 pub use base::ConcurrentHashMap;
-pub use map::Map;
+pub use builder::ConcurrentHashMapBuilder;
+pub use bulk::{Executor, ScopedThreads, Task};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use exclusive::{Drain, IntoIter, IterMut};
+pub use iter::{Iter, Keys, Values};
+pub use locked::LockedHashMap;
+/// The former name of `ConcurrentMap`.
+#[deprecated(note = "renamed to `ConcurrentMap`")]
+pub use map::ConcurrentMap as Map;
+pub use map::{ConcurrentMap, Value};
+pub use map_ref::MapRef;
+#[cfg(feature = "rayon")]
+pub use rayon_impl::ParIter;
+pub use set::ConcurrentHashSet;
+pub use stats::MapStats;
+pub use view::{EntrySetView, KeySetView, OrderedView, ValuesView};
@@ -1,25 +1,80 @@
+use std::borrow::Borrow;
 use std::hash::Hash;
+use std::ops::Deref;
+use std::ptr;
+use std::ptr::NonNull;
 use std::sync::atomic::{AtomicPtr, Ordering};
 
+use crate::ebr::collector::Reclaim;
+
+/// An entry of the map. The value is stored behind an atomic pointer, so that it can be
+/// replaced while readers still hold the old one.
+///
+/// Bins are rebuilt from copies of their nodes when they are split on resize, merged on
+/// shrink, treeified, untreeified or lose their head, while readers may still be walking the
+/// originals. The key is therefore boxed once and owned by the entry rather than by a node:
+/// `moved` copies share it with the original, so every reader sees the same key, and freeing
+/// a node (e.g. through `Guard::defer_destroy`) never drops it. It is only dropped by
+/// `Node::retire` or `drop_key`, for the node of an entry that leaves the map. Every copy made
+/// before was unlinked earlier, and retiring through a guard waits for the readers that may
+/// still hold one.
 pub(crate) struct Node<K, V> {
     pub(crate) hash: usize,
-    pub(crate) key: *const K,
-    pub(crate) val: *mut V,
+    pub(crate) key: Key<K>,
+    pub(crate) val: AtomicPtr<V>,
     pub(crate) next: AtomicPtr<Node<K, V>>,
     pub(crate) prev: AtomicPtr<Node<K, V>>,
 }
 
+/// The boxed key of an entry, shared by the copies of its node. See `Node`.
+pub(crate) struct Key<K>(NonNull<K>);
+
+impl<K> Key<K> {
+    fn new(key: K) -> Key<K> {
+        Key(NonNull::from(Box::leak(Box::new(key))))
+    }
+}
+
+impl<K> Deref for Key<K> {
+    type Target = K;
+
+    fn deref(&self) -> &K {
+        // valid until the entry leaves the map and the readers that saw it are gone
+        unsafe { self.0.as_ref() }
+    }
+}
+
 impl<K, V> PartialEq<Self> for Node<K, V>
 where
     K: Eq,
 {
     fn eq(&self, other: &Self) -> bool {
-        self.hash == other.hash && self.key == other.key
+        self.hash == other.hash && *self.key == *other.key
     }
 }
 
 impl<K, V> Eq for Node<K, V> where K: Eq {}
 
+impl<K, V> Node<K, V> {
+    /// Drops the key of this node.
+    ///
+    /// # Safety
+    /// The entry of the node must have left the map, no copy of the node may be used
+    /// afterwards, and the key must not be dropped twice.
+    pub(crate) unsafe fn drop_key(&mut self) {
+        drop(Box::from_raw(self.key.0.as_ptr()))
+    }
+    /// Reclaims a node that was removed from the map, together with its key.
+    pub(crate) unsafe fn retire<R: Reclaim>(p: *mut Node<K, V>, r: &R) {
+        r.reclaim_with(move || Box::from_raw(p).drop_key())
+    }
+    /// Takes the key back out of a node that was never published, or whose entry left a map
+    /// no other thread can reach anymore.
+    pub(crate) unsafe fn into_key(self) -> K {
+        *Box::from_raw(self.key.0.as_ptr())
+    }
+}
+
 impl<K, V> Node<K, V>
 where
     K: Hash + Eq,
@@ -27,36 +82,41 @@ where
     pub(crate) fn into_box(self) -> *mut Node<K, V> {
         Box::into_raw(Box::new(self))
     }
-    pub(crate) fn new(hash: usize, key: *const K, val: *mut V) -> Node<K, V> {
+    pub(crate) fn new(hash: usize, key: K, val: *mut V) -> Node<K, V> {
+        Self::new_next(hash, key, val, ptr::null_mut())
+    }
+    pub(crate) fn new_next(hash: usize, key: K, val: *mut V, next: *mut Node<K, V>) -> Node<K, V> {
         Self {
             hash,
-            key,
-            val,
-            next: AtomicPtr::default(),
+            key: Key::new(key),
+            val: AtomicPtr::new(val),
+            next: AtomicPtr::new(next),
             prev: AtomicPtr::default(),
         }
     }
-    pub(crate) fn new_next(
-        hash: usize,
-        key: *const K,
-        val: *mut V,
-        next: *mut Node<K, V>,
-    ) -> Node<K, V> {
+    /// Copies this node with `next` as its successor, sharing its key with the copy.
+    /// Must be called while holding the bin lock, and this node must then be unlinked and
+    /// freed without dropping its key.
+    pub(crate) unsafe fn moved(&self, next: *mut Node<K, V>) -> Node<K, V> {
         Self {
-            hash,
-            key,
-            val,
+            hash: self.hash,
+            key: Key(self.key.0),
+            val: AtomicPtr::new(self.val.load(Ordering::Acquire)),
             next: AtomicPtr::new(next),
             prev: AtomicPtr::default(),
         }
     }
-    pub(crate) unsafe fn find(&self, h: usize, key: &K) -> Option<*mut V> {
+    pub(crate) unsafe fn find<Q>(&self, h: usize, key: &Q) -> Option<*mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let mut e = self;
         loop {
-            if e.hash == h && &*e.key == key {
-                return Some(e.val);
+            if e.hash == h && (*e.key).borrow() == key {
+                return Some(e.val.load(Ordering::Acquire));
             }
-            let p = e.next.load(Ordering::Relaxed);
+            let p = e.next.load(Ordering::Acquire);
             if p.is_null() {
                 return None;
             }
@@ -64,3 +124,65 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicIsize, Ordering};
+    use std::thread;
+
+    use crate::concurrent_hash_map::test_util::{Counted, Ids};
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap};
+
+    // keep Miri runs short
+    const SCALE: u64 = if cfg!(miri) { 1 } else { 8 };
+
+    static KEYS: AtomicIsize = AtomicIsize::new(0);
+    static VALUES: AtomicIsize = AtomicIsize::new(0);
+
+    #[test]
+    fn keys_and_values_are_dropped_once() {
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        let (k, v) = (|id| Counted::new(id, &KEYS), |id| Counted::new(id, &VALUES));
+        // every key shares bin 0 up to a table of 256 bins, so it turns into a tree
+        let ids: Vec<u64> = (0..4 * SCALE + 8).map(|i| i * 256).collect();
+        for &id in &ids {
+            map.insert(k(id), v(id));
+            map.insert(k(id), v(id));
+        }
+        assert_eq!(map.stats().tree_bins, 1);
+        for &id in &ids[2..] {
+            assert!(map.remove(&id).is_some());
+        }
+        assert_eq!(map.stats().tree_bins, 0);
+        for id in 0..256 * SCALE {
+            map.compute(k(id), |_, old| old.is_none().then(|| v(id)));
+            map.put_if_absent(k(id), v(id));
+        }
+        assert!(map.compute_if_absent(k(1), |_| unreachable!()).is_some());
+        assert!(map.compute_if_present(&k(2), |_, _| Some(v(2))).is_some());
+        assert!(map.merge(k(3), v(3), |_, _| None).is_none());
+        for id in (0..256 * SCALE).step_by(2) {
+            map.remove(&id);
+        }
+        map.shrink_to_fit();
+        map.clear();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..64 * SCALE {
+                        let id = (i * 7 + t) % (32 * SCALE);
+                        if i % 4 == 3 {
+                            map.remove(&id);
+                        } else {
+                            map.insert(k(id), v(id));
+                        }
+                    }
+                });
+            }
+        });
+        drop(map);
+        assert_eq!(KEYS.load(Ordering::Relaxed), 0);
+        assert_eq!(VALUES.load(Ordering::Relaxed), 0);
+    }
+}
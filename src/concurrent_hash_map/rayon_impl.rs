@@ -0,0 +1,251 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::Ordering;
+
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::iter::{FromParallelIterator, IntoParallelIterator, ParallelExtend, ParallelIterator};
+
+use crate::concurrent_hash_map::base::{BaseNode, ConcurrentHashMap};
+use crate::concurrent_hash_map::iter::Traverser;
+use crate::concurrent_hash_map::map_ref::MapRef;
+use crate::ebr::collector::Guard;
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    /// Returns a parallel iterator over the entries of this map. Like `iter` it is weakly
+    /// consistent, and the references it yields live as long as `guard`, which must come from
+    /// `guard` on this map. `&ConcurrentHashMap` does not implement `IntoParallelIterator`,
+    /// since the references could outlive a guard held by the iterator, but a `MapRef` from
+    /// `pin` does.
+    pub fn par_iter<'g>(&'g self, guard: &'g Guard<'_>) -> ParIter<'g, K, V> {
+        self.check_guard(guard);
+        let tab = unsafe { self.table.load(Ordering::Acquire).as_ref() }.map(|t| &**t);
+        ParIter { tab }
+    }
+}
+
+/// Iterates the map in parallel under the guard held by the handle.
+impl<'g, K, V, S> IntoParallelIterator for &'g MapRef<'_, K, V, S>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher,
+{
+    type Iter = ParIter<'g, K, V>;
+    type Item = (&'g K, &'g V);
+
+    fn into_par_iter(self) -> ParIter<'g, K, V> {
+        self.map().par_iter(self.guard())
+    }
+}
+
+/// A parallel iterator over the entries of a `ConcurrentHashMap`, created by `par_iter`.
+///
+/// It splits the table into ranges of bins like the JDK's `Spliterator`, each traversed like
+/// `Iter`. A range reaching a `ForwardingNode` follows it into the next table, so entries
+/// moved by a concurrent resize are neither skipped nor yielded twice.
+pub struct ParIter<'g, K, V> {
+    tab: Option<&'g [BaseNode<K, V>]>,
+}
+
+impl<'g, K, V> ParallelIterator for ParIter<'g, K, V>
+where
+    K: Sync,
+    V: Sync,
+{
+    type Item = (&'g K, &'g V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        let n = self.tab.map_or(0, |t| t.len());
+        let bins = Bins {
+            tab: self.tab,
+            size: n,
+            index: 0,
+            limit: n,
+        };
+        bridge_unindexed(bins, consumer)
+    }
+}
+
+/// The range of bins `index..limit` of the table the iteration started with.
+struct Bins<'g, K, V> {
+    tab: Option<&'g [BaseNode<K, V>]>,
+    size: usize,
+    index: usize,
+    limit: usize,
+}
+
+impl<'g, K, V> UnindexedProducer for Bins<'g, K, V>
+where
+    K: Sync,
+    V: Sync,
+{
+    type Item = (&'g K, &'g V);
+
+    fn split(self) -> (Self, Option<Self>) {
+        let h = (self.index + self.limit) >> 1;
+        if h <= self.index {
+            return (self, None);
+        }
+        let upper = Bins {
+            tab: self.tab,
+            size: self.size,
+            index: h,
+            limit: self.limit,
+        };
+        (Bins { limit: h, ..self }, Some(upper))
+    }
+
+    fn fold_with<F>(self, mut folder: F) -> F
+    where
+        F: Folder<Self::Item>,
+    {
+        let mut it = Traverser::new(self.tab, self.size, self.index, self.limit);
+        while let Some(p) = it.advance() {
+            folder = folder.consume(unsafe { (&*p.key, &*p.val.load(Ordering::Acquire)) });
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<K, V, S> ParallelExtend<(K, V)> for &ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Sync,
+{
+    /// Inserts the entries of `par_iter` in parallel, presizing the table first if their
+    /// number is known.
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let map = *self;
+        let par_iter = par_iter.into_par_iter();
+        if let Some(size) = par_iter.opt_len().filter(|&size| size > 0) {
+            unsafe { map.try_presize(size, &map.guard()) };
+        }
+        par_iter.for_each_init(
+            || map.guard(),
+            |guard, (key, value)| unsafe {
+                match map.put_val(key, value, false, guard) {
+                    Ok(Some(old)) => guard.defer_destroy(old),
+                    Ok(None) => {}
+                    Err(_) => unreachable!("only rejected if absent was requested"),
+                }
+            },
+        );
+    }
+}
+
+impl<K, V, S> ParallelExtend<(K, V)> for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Sync,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        (&*self).par_extend(par_iter)
+    }
+}
+
+impl<K, V, S> FromParallelIterator<(K, V)> for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Default + Sync,
+{
+    fn from_par_iter<I>(par_iter: I) -> Self
+    where
+        I: IntoParallelIterator<Item = (K, V)>,
+    {
+        let mut map = Self::default();
+        map.par_extend(par_iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::concurrent_hash_map::ConcurrentMap;
+
+    fn entries<S: BuildHasher>(map: &ConcurrentHashMap<u64, u64, S>) -> Vec<(u64, u64)> {
+        let guard = map.guard();
+        let entries = map.par_iter(&guard).map(|(&k, &v)| (k, v)).collect();
+        entries
+    }
+
+    /// Asserts that `entries` holds every key below `n` once, each mapped to itself.
+    fn assert_all_once(entries: Vec<(u64, u64)>, n: u64) {
+        assert_eq!(entries.len() as u64, n);
+        let keys: HashSet<u64> = entries
+            .iter()
+            .map(|&(k, v)| {
+                assert_eq!(k, v);
+                k
+            })
+            .collect();
+        assert_eq!(keys.len() as u64, n);
+        assert!(keys.iter().all(|&k| k < n));
+    }
+
+    #[test]
+    fn par_iter_collects_and_extends() {
+        let map: ConcurrentHashMap<u64, u64> =
+            (0..10_000).into_par_iter().map(|i| (i, i)).collect();
+        assert_eq!(map.size(), 10_000);
+        assert_all_once(entries(&map), 10_000);
+        let pinned = map.pin();
+        let sum: u64 = (&pinned).into_par_iter().map(|(_, &v)| v).sum();
+        assert_eq!(sum, (0..10_000).sum::<u64>());
+        let mut other = ConcurrentHashMap::new();
+        other.par_extend((&pinned).into_par_iter().map(|(&k, &v)| (k, v)));
+        assert_all_once(entries(&other), 10_000);
+    }
+
+    #[test]
+    fn par_iter_during_a_resize() {
+        let n = 1_000;
+        let map = ConcurrentHashMap::new();
+        for i in 0..n {
+            map.insert(i, i);
+        }
+        let len = map.stats().table_len;
+        // hold one bin, so that the resize forwards the others and then waits for it
+        let entry = map.entry(0);
+        thread::scope(|s| {
+            s.spawn(|| map.reserve(4 * n as usize));
+            while !map.stats().resizing {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(map.stats().table_len, len);
+            assert_all_once(entries(&map), n);
+            let pinned = map.pin();
+            assert_all_once(
+                (&pinned).into_par_iter().map(|(&k, &v)| (k, v)).collect(),
+                n,
+            );
+            drop(entry);
+        });
+        assert!(map.stats().table_len > len);
+        assert_all_once(entries(&map), n);
+    }
+}
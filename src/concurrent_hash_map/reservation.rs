@@ -0,0 +1,18 @@
+use std::hash::Hash;
+
+/// A place-holder node used in compute_if_absent and compute. It fills an empty bin while the
+/// bin lock is held, so that no other thread can install a node there before the remapping
+/// function returns.
+pub(crate) struct ReservationNode;
+
+impl ReservationNode {
+    pub(crate) fn new() -> ReservationNode {
+        Self
+    }
+    pub(crate) fn find<Q, V>(&self, _h: usize, _key: &Q) -> Option<*mut V>
+    where
+        Q: ?Sized + Hash + Eq,
+    {
+        None
+    }
+}
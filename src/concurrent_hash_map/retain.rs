@@ -0,0 +1,243 @@
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::Ordering;
+use std::vec;
+
+use crate::concurrent_hash_map::base::{BaseNode, ConcurrentHashMap, NodeEnums};
+use crate::ebr::collector::Guard;
+
+/// Removal by predicate. Each bin is locked once, its remaining nodes are copied into a new
+/// bin like a resize does, and the count is updated once per bin. The predicates run while
+/// holding the bin lock, so they should not write to the map.
+///
+/// Draining a map moves its entries out, which readers of a shared map may still be using.
+/// `drain` therefore takes `&mut self`, and a shared map can only be drained by cloning its
+/// entries with `drain_cloned`.
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Retains only the entries for which `f` returns true. Entries inserted concurrently may
+    /// or may not be visited. Bins moved by a concurrent resize are followed to the table
+    /// they were moved to, so `f` is called once for every other entry.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.remove_where(|k, v| !f(k, v));
+    }
+    /// Removes the entries for which `pred` returns true, see `retain`.
+    /// Returns the number of entries removed.
+    pub fn remove_if<F>(&self, pred: F) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.remove_where(pred)
+    }
+    /// Removes all entries, returning clones of them. This is `drain` for a shared map: the
+    /// keys and values cannot be moved out since concurrent readers may still hold references
+    /// to them. The entries are collected before the iterator is returned.
+    pub fn drain_cloned(&self) -> vec::IntoIter<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::new();
+        self.remove_where(|k, v| {
+            entries.push((k.clone(), v.clone()));
+            true
+        });
+        entries.into_iter()
+    }
+    fn remove_where<F>(&self, mut pred: F) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let guard = self.guard();
+        let mut keep = Vec::new();
+        let tab = match unsafe { self.table.load(Ordering::Acquire).as_ref() } {
+            Some(tab) => tab,
+            None => return 0,
+        };
+        (0..tab.len())
+            .map(|i| unsafe { self.remove_in_bin(tab, i, None, &mut pred, &mut keep, &guard) })
+            .sum()
+    }
+    /// Removes the entries of bin `i` of `tab` for which `pred` returns true, following the
+    /// bin to the bins it was moved to like `Traverser` does. While in a bin merged by a
+    /// shrink, only the entries whose hash matches `filter`, a (mask, index) pair, belong to
+    /// the bin being visited.
+    /// Returns the number of entries removed.
+    unsafe fn remove_in_bin<F>(
+        &self,
+        tab: &[BaseNode<K, V>],
+        i: usize,
+        filter: Option<(usize, usize)>,
+        pred: &mut F,
+        keep: &mut Vec<bool>,
+        guard: &Guard,
+    ) -> usize
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let n = tab.len();
+        loop {
+            let f = &tab[i];
+            let p = f.node.load(Ordering::Acquire);
+            match p.as_ref() {
+                None => return 0,
+                Some(NodeEnums::ForwardingNode(fwd)) => {
+                    let next_tab = &**fwd.next_table;
+                    if next_tab.len() > n {
+                        return self.remove_in_bin(next_tab, i, filter, pred, keep, guard)
+                            + self.remove_in_bin(next_tab, i + n, filter, pred, keep, guard);
+                    }
+                    // merged with other bins, keep the narrowest filter
+                    let filter = match filter {
+                        Some((mask, _)) if mask >= n - 1 => filter,
+                        _ => Some((n - 1, i)),
+                    };
+                    let j = i & (next_tab.len() - 1);
+                    return self.remove_in_bin(next_tab, j, filter, pred, keep, guard);
+                }
+                Some(_) => {
+                    let lock = f.lock();
+                    if f.node.load(Ordering::Acquire) != p {
+                        continue;
+                    }
+                    let removed = self.retain_bin(
+                        f,
+                        p,
+                        |e| {
+                            filter.is_none_or(|(mask, index)| e.hash & mask == index)
+                                && pred(&e.key, &*e.val.load(Ordering::Acquire))
+                        },
+                        keep,
+                        guard,
+                    );
+                    drop(lock);
+                    if removed > 0 {
+                        self.add_count(-(removed as isize), -1, guard);
+                    }
+                    return removed;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::concurrent_hash_map::base::COUNT_UPDATES;
+    use crate::concurrent_hash_map::test_util::Ids;
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap};
+
+    fn count_updates(f: impl FnOnce()) -> usize {
+        COUNT_UPDATES.with(|c| c.set(0));
+        f();
+        COUNT_UPDATES.with(|c| c.get())
+    }
+
+    #[test]
+    fn remove_if_updates_the_count_once_per_bin() {
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        let mut keys: Vec<u64> = (0..200).collect();
+        let n = {
+            for &k in &keys {
+                map.insert(k, k);
+            }
+            map.stats().table_len as u64
+        };
+        // three entries in each of the first 50 bins
+        for k in 0..50 {
+            keys.extend([k + n, k + 2 * n]);
+            map.insert(k + n, k);
+            map.insert(k + 2 * n, k);
+        }
+        assert_eq!(map.stats().table_len as u64, n);
+        let removed = |k: &u64| !(k % n).is_multiple_of(3) && k % n < 100;
+        let bins = (0..n).filter(removed).count();
+        let mut n_removed = 0;
+        let updates = count_updates(|| n_removed = map.remove_if(|k, _| removed(k)));
+        assert_eq!(updates, bins);
+        assert_eq!(n_removed, keys.iter().filter(|k| removed(k)).count());
+        assert_eq!(map.size(), keys.len() - n_removed);
+        let guard = map.guard();
+        assert!(map.iter(&guard).all(|(k, _)| !removed(k)));
+        // nothing to remove, nothing to update
+        assert_eq!(count_updates(|| map.retain(|_, _| true)), 0);
+    }
+
+    /// Removes the odd keys of `map` while `resize` runs, which has moved every bin but bin 0
+    /// once the predicate is called for the entry in bin 0.
+    fn remove_if_during_a_resize<F>(map: &ConcurrentHashMap<u64, u64, Ids>, resize: F)
+    where
+        F: FnOnce() + Send,
+    {
+        let keys: Vec<u64> = {
+            let guard = map.guard();
+            map.keys(&guard).copied().collect()
+        };
+        let started = AtomicBool::new(false);
+        let mut calls = HashMap::new();
+        let removed = thread::scope(|s| {
+            s.spawn(|| {
+                while !started.load(Ordering::Acquire) {
+                    thread::yield_now();
+                }
+                resize();
+            });
+            map.remove_if(|&k, _| {
+                if !started.swap(true, Ordering::AcqRel) {
+                    // hold bin 0 until the resize has moved the bins after it
+                    while !map.stats().resizing {
+                        thread::yield_now();
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                *calls.entry(k).or_insert(0) += 1;
+                !k.is_multiple_of(2)
+            })
+        });
+        assert_eq!(
+            removed,
+            keys.iter().filter(|k| !k.is_multiple_of(2)).count()
+        );
+        for k in &keys {
+            assert_eq!(map.get(k).is_some(), k.is_multiple_of(2));
+        }
+        // the walk followed the moved bins, visiting every entry once
+        assert_eq!(calls.len(), keys.len());
+        assert!(calls.values().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn remove_if_follows_bins_moved_by_a_resize() {
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        for k in 0..40 {
+            map.insert(k, k);
+        }
+        assert_eq!(map.stats().table_len, 64);
+        // a single resize, to 128 bins
+        remove_if_during_a_resize(&map, || map.reserve(50));
+        assert_eq!(map.stats().table_len, 128);
+    }
+
+    #[test]
+    fn remove_if_follows_bins_merged_by_a_shrink() {
+        let map = ConcurrentHashMap::with_capacity_and_hasher(80, Ids::default());
+        // bins 0 to 19 and 64 to 83, merged in pairs when halving the table
+        for k in (0..20).chain(64..84) {
+            map.insert(k, k);
+        }
+        assert_eq!(map.stats().table_len, 128);
+        remove_if_during_a_resize(&map, || map.shrink_to_fit());
+        assert!(map.stats().table_len < 128);
+    }
+}
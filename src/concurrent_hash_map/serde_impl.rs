@@ -0,0 +1,259 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::concurrent_hash_map::base::ConcurrentHashMap;
+use crate::concurrent_hash_map::map::ConcurrentMap;
+use crate::concurrent_hash_map::set::ConcurrentHashSet;
+
+// Upper bound on the memory preallocated from a size hint, which comes from the input and
+// may be bogus.
+const MAX_PREALLOC_BYTES: usize = 1024 * 1024;
+
+fn cautious<T>(hint: Option<usize>) -> usize {
+    let max = MAX_PREALLOC_BYTES / mem::size_of::<T>().max(1);
+    hint.unwrap_or(0).min(max)
+}
+
+/// Serializes a weakly consistent snapshot of the map, as `iter` sees it. The entries are
+/// collected before serializing so that the length written matches them, since formats
+/// such as bincode write it first.
+impl<K, V, S> Serialize for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + Serialize + 'static,
+    V: Send + Serialize + 'static,
+    S: BuildHasher,
+{
+    fn serialize<T>(&self, serializer: T) -> Result<T::Ok, T::Error>
+    where
+        T: Serializer,
+    {
+        let guard = self.guard();
+        let entries: Vec<_> = self.iter(&guard).collect();
+        let mut map = serializer.serialize_map(Some(entries.len()))?;
+        for (k, v) in entries {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, K, V, S> Deserialize<'de> for ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + Deserialize<'de> + 'static,
+    V: Send + Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+struct MapVisitor<K, V, S>(PhantomData<(K, V, S)>);
+
+impl<'de, K, V, S> Visitor<'de> for MapVisitor<K, V, S>
+where
+    K: Hash + Eq + Send + Deserialize<'de> + 'static,
+    V: Send + Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    type Value = ConcurrentHashMap<K, V, S>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let capacity = cautious::<(K, V)>(access.size_hint());
+        let map = ConcurrentHashMap::with_capacity_and_hasher(capacity, S::default());
+        while let Some((k, v)) = access.next_entry()? {
+            map.insert(k, v);
+        }
+        Ok(map)
+    }
+}
+
+/// Serializes a weakly consistent snapshot of the set, like the map does.
+impl<K, S> Serialize for ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + Serialize + 'static,
+    S: BuildHasher,
+{
+    fn serialize<T>(&self, serializer: T) -> Result<T::Ok, T::Error>
+    where
+        T: Serializer,
+    {
+        let guard = self.guard();
+        let keys: Vec<_> = self.iter(&guard).collect();
+        let mut seq = serializer.serialize_seq(Some(keys.len()))?;
+        for k in keys {
+            seq.serialize_element(k)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, K, S> Deserialize<'de> for ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(SetVisitor(PhantomData))
+    }
+}
+
+struct SetVisitor<K, S>(PhantomData<(K, S)>);
+
+impl<'de, K, S> Visitor<'de> for SetVisitor<K, S>
+where
+    K: Hash + Eq + Send + Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    type Value = ConcurrentHashSet<K, S>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A>(self, mut access: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let capacity = cautious::<K>(access.size_hint());
+        let set = ConcurrentHashSet::with_capacity_and_hasher(capacity, S::default());
+        while let Some(k) = access.next_element()? {
+            set.insert(k);
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::mem;
+
+    use serde::de::value::{Error, MapAccessDeserializer, SeqAccessDeserializer, U64Deserializer};
+    use serde::de::{Deserialize, DeserializeSeed, MapAccess, SeqAccess};
+
+    use super::MAX_PREALLOC_BYTES;
+    use crate::concurrent_hash_map::test_util::Ids;
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentHashSet, ConcurrentMap};
+
+    #[test]
+    fn map_round_trips() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..100u64 {
+            map.insert(i, format!("v{i}"));
+        }
+        let json = serde_json::to_string(&map).unwrap();
+        let back: ConcurrentHashMap<u64, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.size(), 100);
+        for i in 0..100 {
+            assert_eq!(back.get(&i).as_deref(), Some(&format!("v{i}")));
+        }
+        // an empty map, and a later duplicate key winning like in `insert`
+        let empty: ConcurrentHashMap<u64, String> = serde_json::from_str("{}").unwrap();
+        assert!(empty.is_empty());
+        let dup: ConcurrentHashMap<u64, u64> = serde_json::from_str(r#"{"1":1,"1":2}"#).unwrap();
+        assert_eq!(dup.get(&1).as_deref(), Some(&2));
+    }
+
+    #[test]
+    fn map_round_trips_with_a_custom_hasher() {
+        let map = ConcurrentHashMap::with_hasher(Ids::default());
+        for i in 0..100u64 {
+            map.insert(i * 16, i);
+        }
+        let json = serde_json::to_string(&map).unwrap();
+        let back: ConcurrentHashMap<u64, u64, Ids> = serde_json::from_str(&json).unwrap();
+        let guard = back.guard();
+        let entries: BTreeMap<_, _> = back.iter(&guard).map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(entries, (0..100).map(|i| (i * 16, i)).collect());
+        // matching the written form of the other map
+        let expected: BTreeMap<u64, u64> = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn set_round_trips() {
+        let set: ConcurrentHashSet<String> = (0..50).map(|i| format!("k{i}")).collect();
+        let json = serde_json::to_string(&set).unwrap();
+        let back: ConcurrentHashSet<String, Ids> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.len(), 50);
+        assert!((0..50).all(|i| back.contains(format!("k{i}").as_str())));
+        let dup: ConcurrentHashSet<u64> = serde_json::from_str("[3,1,3]").unwrap();
+        let guard = dup.guard();
+        let keys: BTreeSet<_> = dup.iter(&guard).copied().collect();
+        assert_eq!(keys, BTreeSet::from([1, 3]));
+    }
+
+    /// Yields `n` entries or elements while claiming there are `usize::MAX` of them.
+    struct Lying {
+        n: u64,
+    }
+
+    impl<'de> MapAccess<'de> for Lying {
+        type Error = Error;
+
+        fn next_key_seed<K: DeserializeSeed<'de>>(
+            &mut self,
+            seed: K,
+        ) -> Result<Option<K::Value>, Error> {
+            if self.n == 0 {
+                return Ok(None);
+            }
+            self.n -= 1;
+            seed.deserialize(U64Deserializer::new(self.n)).map(Some)
+        }
+        fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+            seed.deserialize(U64Deserializer::new(self.n))
+        }
+        fn size_hint(&self) -> Option<usize> {
+            Some(usize::MAX)
+        }
+    }
+
+    impl<'de> SeqAccess<'de> for Lying {
+        type Error = Error;
+
+        fn next_element_seed<T: DeserializeSeed<'de>>(
+            &mut self,
+            seed: T,
+        ) -> Result<Option<T::Value>, Error> {
+            self.next_key_seed(seed)
+        }
+        fn size_hint(&self) -> Option<usize> {
+            Some(usize::MAX)
+        }
+    }
+
+    #[test]
+    fn lying_length_hints_cap_preallocation() {
+        // a table sized for the capped hint, rather than the largest table
+        let max_bins = 4 * MAX_PREALLOC_BYTES / mem::size_of::<(u64, u64)>();
+        let map =
+            ConcurrentHashMap::<u64, u64>::deserialize(MapAccessDeserializer::new(Lying { n: 3 }))
+                .unwrap();
+        assert_eq!(map.size(), 3);
+        assert!(map.stats().table_len <= max_bins);
+        let set = ConcurrentHashSet::<u64>::deserialize(SeqAccessDeserializer::new(Lying { n: 3 }))
+            .unwrap();
+        assert_eq!(set.len(), 3);
+        assert!(set.map().stats().table_len <= 4 * MAX_PREALLOC_BYTES / mem::size_of::<u64>());
+    }
+}
@@ -0,0 +1,247 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use crate::concurrent_hash_map::base::ConcurrentHashMap;
+use crate::concurrent_hash_map::iter::Keys;
+use crate::concurrent_hash_map::map::ConcurrentMap;
+use crate::ebr::collector::Guard;
+
+/// A concurrent set backed by a `ConcurrentHashMap` mapping every key to `()`, like the JDK's
+/// `ConcurrentHashMap.newKeySet()`. Boxing the zero-sized value does not allocate, so entries
+/// cost no more than the key and its node.
+pub struct ConcurrentHashSet<K, S = RandomState> {
+    map: ConcurrentHashMap<K, (), S>,
+}
+
+impl<K> ConcurrentHashSet<K, RandomState>
+where
+    K: Hash + Eq + Send + 'static,
+{
+    pub fn new() -> ConcurrentHashSet<K> {
+        Self::with_hasher(RandomState::new())
+    }
+    /// Creates an empty set with an initial table sized to accommodate `capacity` elements
+    /// without resizing.
+    pub fn with_capacity(capacity: usize) -> ConcurrentHashSet<K> {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K, S> Default for ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + 'static,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, S> ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + 'static,
+    S: BuildHasher,
+{
+    /// Creates an empty set which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self {
+            map: ConcurrentHashMap::with_hasher(hash_builder),
+        }
+    }
+    /// Creates an empty set which will use `hash_builder` to hash keys, with an initial table
+    /// sized to accommodate `capacity` elements without resizing.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self {
+            map: ConcurrentHashMap::with_capacity_and_hasher(capacity, hash_builder),
+        }
+    }
+    /// Returns the map backing this set.
+    pub fn map(&self) -> &ConcurrentHashMap<K, (), S> {
+        &self.map
+    }
+    /// Pins the collector of the backing map, see `ConcurrentHashMap::guard`.
+    pub fn guard(&self) -> Guard<'_> {
+        self.map.guard()
+    }
+    /// Returns the number of keys in this set.
+    pub fn len(&self) -> usize {
+        self.map.size()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Adds `key` to this set if it is not already present.
+    /// Returns true if the key was added.
+    pub fn insert(&self, key: K) -> bool {
+        self.map.put_if_absent(key, ()).is_none()
+    }
+    /// Removes `key` from this set.
+    /// Returns true if the key was present.
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.remove(key).is_some()
+    }
+    /// Returns true if this set contains `key`.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.contains_key(key)
+    }
+    /// Removes all keys from this set.
+    pub fn clear(&self) {
+        self.map.clear()
+    }
+    /// Returns an iterator over the keys of this set, in arbitrary order. See
+    /// `ConcurrentHashMap::iter` for its guarantees.
+    pub fn iter<'g>(&'g self, guard: &'g Guard<'_>) -> Keys<'g, K, ()> {
+        self.map.keys(guard)
+    }
+    /// Retains only the keys for which `f` returns true. See `ConcurrentHashMap::retain`.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K) -> bool,
+    {
+        self.map.retain(|key, _| f(key))
+    }
+    /// Returns true if every key of this set is contained in `other`.
+    pub fn is_subset<T>(&self, other: &ConcurrentHashSet<K, T>) -> bool
+    where
+        T: BuildHasher,
+    {
+        let guard = self.guard();
+        let subset = self.iter(&guard).all(|key| other.contains(key));
+        subset
+    }
+    /// Returns true if every key of `other` is contained in this set.
+    pub fn is_superset<T>(&self, other: &ConcurrentHashSet<K, T>) -> bool
+    where
+        T: BuildHasher,
+    {
+        other.is_subset(self)
+    }
+    /// Returns true if this set has no key in common with `other`.
+    pub fn is_disjoint<T>(&self, other: &ConcurrentHashSet<K, T>) -> bool
+    where
+        T: BuildHasher,
+    {
+        let guard = self.guard();
+        let disjoint = !self.iter(&guard).any(|key| other.contains(key));
+        disjoint
+    }
+}
+
+impl<K, S> Extend<K> for &ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + 'static,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        for key in iter {
+            self.insert(key);
+        }
+    }
+}
+
+impl<K, S> Extend<K> for ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + 'static,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        (&*self).extend(iter)
+    }
+}
+
+impl<K, S> FromIterator<K> for ConcurrentHashSet<K, S>
+where
+    K: Hash + Eq + Send + 'static,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+    use std::thread;
+
+    use crate::concurrent_hash_map::test_util::Ids;
+    use crate::concurrent_hash_map::ConcurrentHashSet;
+
+    fn sorted<S: BuildHasher>(set: &ConcurrentHashSet<u64, S>) -> Vec<u64> {
+        let guard = set.guard();
+        let mut keys: Vec<_> = set.iter(&guard).copied().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn insert_remove_contains_and_len() {
+        let set = ConcurrentHashSet::new();
+        assert!(set.is_empty());
+        thread::scope(|s| {
+            for t in 0..4 {
+                let set = &set;
+                s.spawn(move || {
+                    for i in 0..256 {
+                        set.insert(i * 4 + t % 2);
+                    }
+                });
+            }
+        });
+        assert_eq!(set.len(), 512);
+        assert!(!set.insert(4));
+        assert!(set.contains(&4) && set.contains(&5) && !set.contains(&6));
+        assert!(set.remove(&4));
+        assert!(!set.remove(&4));
+        assert!(!set.contains(&4));
+        assert_eq!(set.len(), 511);
+        set.clear();
+        assert!(set.is_empty());
+        assert!(set.insert(4));
+    }
+
+    #[test]
+    fn retain_keeps_matching_keys() {
+        let set: ConcurrentHashSet<u64> = (0..100).collect();
+        set.retain(|k| k % 3 == 0);
+        assert_eq!(sorted(&set), (0..100).step_by(3).collect::<Vec<_>>());
+        assert_eq!(set.len(), 34);
+    }
+
+    #[test]
+    fn subsets_supersets_and_disjoint_sets() {
+        let all: ConcurrentHashSet<u64> = (0..10).collect();
+        let even = ConcurrentHashSet::with_hasher(Ids::default());
+        let odd = ConcurrentHashSet::with_hasher(Ids::default());
+        (&even).extend((0..10).step_by(2));
+        (&odd).extend((1..10).step_by(2));
+        assert!(even.is_subset(&all) && !all.is_subset(&even));
+        assert!(all.is_superset(&odd) && !odd.is_superset(&all));
+        assert!(even.is_disjoint(&odd) && !even.is_disjoint(&all));
+        assert!(even.is_subset(&even) && even.is_superset(&even));
+        let empty = ConcurrentHashSet::<u64, RandomState>::new();
+        assert!(empty.is_subset(&odd) && odd.is_superset(&empty) && empty.is_disjoint(&all));
+    }
+
+    #[test]
+    fn extend_and_from_iter_skip_duplicates() {
+        let mut set: ConcurrentHashSet<u64, Ids> = [3, 1, 3, 2, 1].into_iter().collect();
+        assert_eq!(sorted(&set), [1, 2, 3]);
+        set.extend([2, 4, 4, 5]);
+        (&set).extend(6..8);
+        assert_eq!(sorted(&set), [1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(set.len(), 7);
+    }
+}
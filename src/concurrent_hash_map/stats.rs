@@ -0,0 +1,253 @@
+use std::hash::{BuildHasher, Hash};
+#[cfg(feature = "stats")]
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use crate::concurrent_hash_map::base::{BaseNode, ConcurrentHashMap, NodeEnums, MAX_RESIZERS};
+
+/// A snapshot of the internals of a map, returned by `ConcurrentHashMap::stats`.
+///
+/// It is gathered without stopping concurrent writers, so its fields may not agree with each
+/// other exactly.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MapStats {
+    /// Length of the table, 0 before the first insertion.
+    pub table_len: usize,
+    /// Number of mappings, as counted by `size`.
+    pub entries: usize,
+    /// `bin_lengths[n]` is the number of bins holding `n` entries. Bins already moved by a
+    /// resize in flight are counted as the bins they were moved to.
+    pub bin_lengths: Vec<usize>,
+    /// Number of bins that are trees.
+    pub tree_bins: usize,
+    /// Whether a resize is in flight.
+    pub resizing: bool,
+    /// Number of threads currently transferring bins to the next table.
+    pub resizers: usize,
+    /// Number of counter cells spreading the entry count.
+    pub counter_cells: usize,
+    /// Number of completed resizes since the map was created.
+    #[cfg(feature = "stats")]
+    pub resizes: usize,
+    /// Number of bins converted to trees since the map was created.
+    #[cfg(feature = "stats")]
+    pub treeifies: usize,
+    /// Number of tree bins converted back to lists since the map was created.
+    #[cfg(feature = "stats")]
+    pub untreeifies: usize,
+}
+
+/// Cumulative event counters of a map. Without the `stats` feature it has no fields, and
+/// recording an event does nothing.
+#[derive(Default)]
+pub(crate) struct Counters {
+    #[cfg(feature = "stats")]
+    resizes: AtomicUsize,
+    #[cfg(feature = "stats")]
+    treeifies: AtomicUsize,
+    #[cfg(feature = "stats")]
+    untreeifies: AtomicUsize,
+}
+
+impl Counters {
+    #[inline]
+    pub(crate) fn resized(&self) {
+        #[cfg(feature = "stats")]
+        self.resizes.fetch_add(1, Ordering::Relaxed);
+    }
+    #[inline]
+    pub(crate) fn treeified(&self) {
+        #[cfg(feature = "stats")]
+        self.treeifies.fetch_add(1, Ordering::Relaxed);
+    }
+    #[inline]
+    pub(crate) fn untreeified(&self) {
+        #[cfg(feature = "stats")]
+        self.untreeifies.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns a snapshot of the internals of this map, for tuning. Walks every bin.
+    pub fn stats(&self) -> MapStats {
+        let _guard = self.guard();
+        let mut stats = MapStats {
+            entries: self.sum_count().max(0) as usize,
+            resizing: !self.next_table.load(Ordering::Acquire).is_null(),
+            ..MapStats::default()
+        };
+        let sc = self.size_ctl.load(Ordering::Acquire);
+        if sc < -1 {
+            // the low bits hold 1 + the number of resizers
+            stats.resizers = ((sc & MAX_RESIZERS) - 1).max(0) as usize;
+        }
+        unsafe {
+            if let Some(cc) = self.counter_cells.load(Ordering::Acquire).as_ref() {
+                stats.counter_cells = cc.len();
+            }
+            if let Some(tab) = self.table.load(Ordering::Acquire).as_ref() {
+                stats.table_len = tab.len();
+                for i in 0..tab.len() {
+                    Self::bin_stats(tab, i, &mut stats);
+                }
+            }
+        }
+        #[cfg(feature = "stats")]
+        {
+            stats.resizes = self.counters.resizes.load(Ordering::Relaxed);
+            stats.treeifies = self.counters.treeifies.load(Ordering::Relaxed);
+            stats.untreeifies = self.counters.untreeifies.load(Ordering::Relaxed);
+        }
+        stats
+    }
+    /// Adds bin `i` of `tab` to `stats`, or the bins it was moved to if it was moved.
+    unsafe fn bin_stats(tab: &[BaseNode<K, V>], i: usize, stats: &mut MapStats) {
+        let mut e = match tab[i].node.load(Ordering::Acquire).as_ref() {
+            Some(NodeEnums::ForwardingNode(f)) => {
+                let next_tab = &**f.next_table;
+                if next_tab.len() > tab.len() {
+                    Self::bin_stats(next_tab, i, stats);
+                    Self::bin_stats(next_tab, i + tab.len(), stats);
+                } else if i < next_tab.len() {
+                    // merged with bin i + next_tab.len(), count it once
+                    Self::bin_stats(next_tab, i, stats);
+                }
+                return;
+            }
+            Some(NodeEnums::Node(head)) => Some(head),
+            Some(NodeEnums::TreeBin(t)) => {
+                stats.tree_bins += 1;
+                t.first.load(Ordering::Acquire).as_ref()
+            }
+            Some(NodeEnums::ReservationNode(_)) | None => None,
+        };
+        let mut len = 0;
+        while let Some(node) = e {
+            len += 1;
+            e = node.next.load(Ordering::Acquire).as_ref();
+        }
+        if stats.bin_lengths.len() <= len {
+            stats.bin_lengths.resize(len + 1, 0);
+        }
+        stats.bin_lengths[len] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::concurrent_hash_map::test_util::Ids;
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap, MapStats};
+
+    /// Returns a map of 64 bins holding 3 keys in bin 0, 2 in bin 1, one in each of bins 2 to
+    /// 9, and a tree of 9 keys in bin 63.
+    fn filled() -> ConcurrentHashMap<u64, u64, Ids> {
+        let map = ConcurrentHashMap::with_capacity_and_hasher(32, Ids::default());
+        let keys = [0, 64, 128, 1, 65].into_iter().chain(2..10);
+        for k in keys.chain((0..9).map(|i| 63 + 64 * i)) {
+            map.insert(k, k);
+        }
+        map
+    }
+
+    fn histogram(lengths: &[(usize, usize)]) -> Vec<usize> {
+        let mut bin_lengths = vec![0; lengths.iter().map(|&(n, _)| n + 1).max().unwrap()];
+        for &(n, bins) in lengths {
+            bin_lengths[n] = bins;
+        }
+        bin_lengths
+    }
+
+    #[test]
+    fn bin_lengths_count_colliding_keys() {
+        let stats = ConcurrentHashMap::<u64, u64>::new().stats();
+        assert_eq!((stats.table_len, stats.entries), (0, 0));
+        assert!(stats.bin_lengths.is_empty());
+        let stats = filled().stats();
+        assert_eq!(
+            (stats.table_len, stats.entries, stats.tree_bins),
+            (64, 22, 1)
+        );
+        let expected = histogram(&[(0, 53), (1, 8), (2, 1), (3, 1), (9, 1)]);
+        assert_eq!(stats.bin_lengths, expected);
+        assert!(!stats.resizing);
+        assert_eq!(stats.resizers, 0);
+    }
+
+    #[test]
+    fn bin_lengths_follow_moved_bins_during_a_resize() {
+        let map = filled();
+        // hold bin 0, so that the resize moves the others to a table of 128 bins and waits
+        let entry = map.entry(0);
+        let stats = thread::scope(|s| {
+            s.spawn(|| map.reserve(30));
+            while !map.stats().resizing {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(50));
+            let stats = map.stats();
+            drop(entry);
+            stats
+        });
+        let MapStats {
+            table_len,
+            entries,
+            tree_bins,
+            resizing,
+            resizers,
+            ..
+        } = stats;
+        assert_eq!((table_len, entries, resizing, resizers), (64, 22, true, 1));
+        // bin 1 and the tree are split, the tree into lists of 5 and 4 keys, bin 0 is counted
+        // where it is
+        assert_eq!(tree_bins, 0);
+        let expected = histogram(&[(0, 114), (1, 10), (3, 1), (4, 1), (5, 1)]);
+        assert_eq!(stats.bin_lengths, expected);
+        // and then bin 0 too
+        let stats = map.stats();
+        assert_eq!((stats.table_len, stats.resizing), (128, false));
+        let expected = histogram(&[(0, 114), (1, 11), (2, 1), (4, 1), (5, 1)]);
+        assert_eq!(stats.bin_lengths, expected);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn counters_record_treeifies_untreeifies_and_resizes() {
+        let map = ConcurrentHashMap::with_capacity_and_hasher(32, Ids::default());
+        let stats = map.stats();
+        assert_eq!(
+            (stats.resizes, stats.treeifies, stats.untreeifies),
+            (0, 0, 0)
+        );
+        for i in 0..9 {
+            map.insert(63 + 64 * i, i);
+        }
+        let stats = map.stats();
+        assert_eq!(
+            (stats.resizes, stats.treeifies, stats.untreeifies),
+            (0, 1, 0)
+        );
+        // splits the tree into lists of 5 and 4 keys
+        map.reserve(50);
+        let stats = map.stats();
+        assert_eq!(
+            (stats.resizes, stats.treeifies, stats.untreeifies),
+            (1, 1, 2)
+        );
+        for i in 0..9 {
+            map.insert(63 + 128 * i, i);
+        }
+        map.reserve(1000);
+        let stats = map.stats();
+        assert!(stats.resizes > 1);
+        assert_eq!(stats.treeifies, 2);
+        assert!(stats.untreeifies > 2);
+    }
+}
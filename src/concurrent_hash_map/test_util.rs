@@ -0,0 +1,84 @@
+use std::borrow::Borrow;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+/// Hashes integers to themselves, modulo `N` unless it is 0, so that tests choose the bins of
+/// their keys. Any other data is folded into the hash.
+#[derive(Default)]
+pub(crate) struct IdHasher<const N: u64 = 0>(u64);
+
+impl<const N: u64> Hasher for IdHasher<N> {
+    fn finish(&self) -> u64 {
+        if N == 0 {
+            self.0
+        } else {
+            self.0 % N
+        }
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = self.0.rotate_left(8) ^ b as u64;
+        }
+    }
+    fn write_u8(&mut self, n: u8) {
+        self.0 = n as u64;
+    }
+    fn write_u16(&mut self, n: u16) {
+        self.0 = n as u64;
+    }
+    fn write_u32(&mut self, n: u32) {
+        self.0 = n as u64;
+    }
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+    fn write_usize(&mut self, n: usize) {
+        self.0 = n as u64;
+    }
+}
+
+/// Hashes integer keys to themselves.
+pub(crate) type Ids = BuildHasherDefault<IdHasher>;
+
+/// Hashes integer keys to one of `N` hashes, so that bins fill with colliding keys.
+pub(crate) type FewIds<const N: u64> = BuildHasherDefault<IdHasher<N>>;
+
+/// A key or value counting its live instances in `live`, so that tests can check that a map
+/// drops everything it was given exactly once. Hashes, compares and borrows as its `id`.
+pub(crate) struct Counted {
+    pub(crate) id: u64,
+    live: &'static AtomicIsize,
+}
+
+impl Counted {
+    pub(crate) fn new(id: u64, live: &'static AtomicIsize) -> Self {
+        live.fetch_add(1, Ordering::Relaxed);
+        Counted { id, live }
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.live.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Hash for Counted {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl PartialEq for Counted {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Counted {}
+
+impl Borrow<u64> for Counted {
+    fn borrow(&self) -> &u64 {
+        &self.id
+    }
+}
@@ -1,10 +1,9 @@
-use std::any::Any;
+use std::borrow::Borrow;
+use std::cmp::Ordering as KeyOrdering;
 use std::hash::Hash;
-use std::ops::Deref;
 use std::sync::atomic::{AtomicIsize, AtomicPtr, Ordering};
-use std::sync::Arc;
 use std::thread::Thread;
-use std::{cmp, ptr, thread};
+use std::{mem, ptr, thread};
 
 use crate::concurrent_hash_map::node::Node;
 use crate::ebr::collector::Guard;
@@ -46,15 +45,6 @@ where
             red: false,
         }
     }
-    pub(crate) fn new_right(node: *mut Node<K, V>, right: *mut TreeNode<K, V>) -> TreeNode<K, V> {
-        Self {
-            node,
-            parent: ptr::null_mut(),
-            left: ptr::null_mut(),
-            right,
-            red: false,
-        }
-    }
     pub(crate) fn new_parent(node: *mut Node<K, V>, parent: *mut TreeNode<K, V>) -> TreeNode<K, V> {
         Self {
             node,
@@ -65,41 +55,57 @@ where
         }
     }
     /// Returns the TreeNode (or null if not found) for the given key starting at given root.
-    pub(crate) unsafe fn find_tree_node(&self, h: usize, key: &K) -> Option<&TreeNode<K, V>> {
-        let mut p = self;
+    /// Takes and returns raw pointers, so that callers holding the bin lock may update the node.
+    pub(crate) unsafe fn find_tree_node<Q>(
+        mut p: *mut TreeNode<K, V>,
+        h: usize,
+        key: &Q,
+    ) -> Option<*mut TreeNode<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         loop {
-            let pl = p.left;
-            let pr = p.right;
-            let ph = (&*p.node).hash;
+            let pl = (*p).left;
+            let pr = (*p).right;
+            let ph = (*(*p).node).hash;
             if ph > h {
                 if pl.is_null() {
                     return None;
                 }
-                p = &*pl;
+                p = pl;
             } else if ph < h {
                 if pr.is_null() {
                     return None;
                 }
-                p = &*pr;
-            } else if &*(&*p.node).key == key {
+                p = pr;
+            } else if (*(*(*p).node).key).borrow() == key {
                 return Some(p);
             } else if pl.is_null() {
                 if pr.is_null() {
                     return None;
                 }
-                p = &*pr
+                p = pr
             } else if pr.is_null() {
-                p = &*pl
+                p = pl
             } else {
-                if let Some(q) = (*pr).find_tree_node(h, key) {
+                if let Some(q) = Self::find_tree_node(pr, h, key) {
                     return Some(q);
                 }
-                p = &*pl
+                p = pl
             }
         }
     }
 }
 
+/// Orders keys with equal hashes inside a tree bin, standing in for the JDK's
+/// compareComparables. It must agree with `Eq`.
+pub(crate) type KeyCmp<K> = fn(&K, &K) -> KeyOrdering;
+
+/// The order of a looked up key relative to the key of a node, used to descend through equal
+/// hashes in ordered bins. Lookups without one search all nodes with equal hashes.
+pub(crate) type KeyOrd<'a, K> = Option<&'a dyn Fn(&K) -> KeyOrdering>;
+
 // values for lockState
 const WRITER: isize = 1;
 // set while holding write lock
@@ -112,6 +118,7 @@ pub(crate) struct TreeBin<K, V> {
     pub(crate) first: AtomicPtr<Node<K, V>>,
     waiter: AtomicPtr<Thread>,
     lock_state: AtomicIsize,
+    cmp: Option<KeyCmp<K>>,
 }
 
 impl<K, V> Drop for TreeBin<K, V> {
@@ -122,13 +129,18 @@ impl<K, V> Drop for TreeBin<K, V> {
             if !self.root.is_null() {
                 drop(Box::from_raw(self.root));
             }
+            let waiter = *self.waiter.get_mut();
+            if !waiter.is_null() {
+                drop(Box::from_raw(waiter));
+            }
         }
     }
 }
 
 impl<K, V> TreeBin<K, V> {
-    /// Creates bin with initial set of nodes headed by b.
-    pub(crate) unsafe fn new(b: *mut TreeNode<K, V>) -> TreeBin<K, V> {
+    /// Creates bin with initial set of nodes headed by b. Nodes with equal hashes are ordered
+    /// by `cmp` if given, otherwise they are only reachable by scanning.
+    pub(crate) unsafe fn new(b: *mut TreeNode<K, V>, cmp: Option<KeyCmp<K>>) -> TreeBin<K, V> {
         let first = AtomicPtr::new((&*b).node);
         let mut r = ptr::null_mut::<TreeNode<K, V>>();
         let mut x = b;
@@ -146,7 +158,13 @@ impl<K, V> TreeBin<K, V> {
                 loop {
                     let ph = (*(*p).node).hash;
                     let xp = p;
-                    if ph >= h {
+                    let left = match cmp {
+                        Some(cmp) if ph == h => {
+                            cmp(&*(*(*x).node).key, &*(*(*p).node).key) != KeyOrdering::Greater
+                        }
+                        _ => ph >= h,
+                    };
+                    if left {
                         p = (*p).left;
                     } else {
                         p = (*p).right;
@@ -154,7 +172,7 @@ impl<K, V> TreeBin<K, V> {
 
                     if p.is_null() {
                         (*x).parent = xp;
-                        if ph >= h {
+                        if left {
                             (*xp).left = x;
                         } else {
                             (*xp).right = x;
@@ -174,6 +192,7 @@ impl<K, V> TreeBin<K, V> {
             first,
             waiter: Default::default(),
             lock_state: Default::default(),
+            cmp,
         }
     }
     unsafe fn balance_insertion(
@@ -181,7 +200,7 @@ impl<K, V> TreeBin<K, V> {
         mut x: *mut TreeNode<K, V>,
     ) -> *mut TreeNode<K, V> {
         (*x).red = true;
-        let mut xpp = ptr::null_mut::<TreeNode<K, V>>();
+        let mut xpp;
         loop {
             let mut xp = (*x).parent;
             if xp.is_null() {
@@ -347,13 +366,13 @@ impl<K, V> TreeBin<K, V> {
     /// Red-black tree methods, all adapted from CLR
     unsafe fn rotate_left(
         mut root: *mut TreeNode<K, V>,
-        mut p: *mut TreeNode<K, V>,
+        p: *mut TreeNode<K, V>,
     ) -> *mut TreeNode<K, V> {
         if !p.is_null() {
             let r = (*p).right;
             if !r.is_null() {
-                (*r).right = (*r).left;
-                let rl = (*r).right;
+                (*p).right = (*r).left;
+                let rl = (*p).right;
                 if !rl.is_null() {
                     (*rl).parent = p;
                 }
@@ -375,7 +394,7 @@ impl<K, V> TreeBin<K, V> {
     }
     unsafe fn rotate_right(
         mut root: *mut TreeNode<K, V>,
-        mut p: *mut TreeNode<K, V>,
+        p: *mut TreeNode<K, V>,
     ) -> *mut TreeNode<K, V> {
         if !p.is_null() {
             let l = (*p).left;
@@ -415,9 +434,7 @@ impl<K, V> TreeBin<K, V> {
                     if waiting {
                         let shared = waiter.swap(ptr::null_mut(), Ordering::Relaxed);
                         if !shared.is_null() {
-                            unsafe {
-                                guard.defer_destroy(shared);
-                            }
+                            unsafe { guard.defer_destroy(shared) };
                         }
                     }
                     return;
@@ -433,9 +450,7 @@ impl<K, V> TreeBin<K, V> {
                         Ordering::Relaxed,
                     );
                     if !shared.is_null() {
-                        unsafe {
-                            guard.defer_destroy(shared);
-                        }
+                        unsafe { guard.defer_destroy(shared) };
                     }
                 }
             } else if waiting {
@@ -465,31 +480,27 @@ where
 {
     /// Returns matching node or null if none. Tries to search using tree comparisons from root,
     /// but continues linear search when lock not available.
-    pub(crate) unsafe fn find(&self, h: usize, key: &K) -> Option<*mut V> {
+    pub(crate) unsafe fn find<Q>(&self, h: usize, key: &Q, ord: KeyOrd<'_, K>) -> Option<*mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
         let mut e_shared = self.first.load(Ordering::Acquire);
         let lock_state = &self.lock_state;
         while let Some(e) = e_shared.as_ref() {
             let s = lock_state.load(Ordering::Acquire);
             if s & (WAITER | WRITER) != 0 {
-                if e.hash == h && &*e.key == key {
-                    return Some(e.val);
+                if e.hash == h && (*e.key).borrow() == key {
+                    return Some(e.val.load(Ordering::Acquire));
                 }
                 e_shared = e.next.load(Ordering::Acquire);
             } else if lock_state
                 .compare_exchange(s, s + READER, Ordering::AcqRel, Ordering::Relaxed)
                 .is_ok()
             {
-                let root = self.root;
-                // not null
-                let p = if !root.is_null() {
-                    if let Some(t) = (*root).find_tree_node(h, key) {
-                        Some((*t.node).val)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                };
+                let p = self
+                    .find_node(h, key, ord)
+                    .map(|t| (*(*t).node).val.load(Ordering::Acquire));
                 if lock_state.fetch_add(-READER, Ordering::AcqRel) == (READER | WAITER) {
                     let w = self.waiter.load(Ordering::Acquire);
                     if let Some(w) = w.as_ref() {
@@ -501,16 +512,59 @@ where
         }
         None
     }
+    /// Returns the tree node holding `key`, descending through equal hashes by `ord` if this
+    /// bin is ordered and the lookup has one, or else searching all nodes with equal hashes.
+    /// Must be called while holding the bin lock or a read lock.
+    pub(crate) unsafe fn find_node<Q>(
+        &self,
+        h: usize,
+        key: &Q,
+        ord: KeyOrd<'_, K>,
+    ) -> Option<*mut TreeNode<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let ord = match ord {
+            Some(ord) if self.cmp.is_some() => ord,
+            _ if self.root.is_null() => return None,
+            _ => return TreeNode::find_tree_node(self.root, h, key),
+        };
+        let mut p = self.root;
+        while let Some(pn) = p.as_ref() {
+            let ph = (*pn.node).hash;
+            p = if ph > h {
+                pn.left
+            } else if ph < h {
+                pn.right
+            } else {
+                match ord(&*(*pn.node).key) {
+                    KeyOrdering::Less => pn.left,
+                    KeyOrdering::Greater => pn.right,
+                    KeyOrdering::Equal => return Some(p),
+                }
+            };
+        }
+        None
+    }
+    /// Returns the tree node holding `key`, descending through equal hashes by the key order
+    /// of this bin if it has one. Must be called while holding the bin lock or a read lock.
+    pub(crate) unsafe fn find_key_node(&self, h: usize, key: &K) -> Option<*mut TreeNode<K, V>> {
+        match self.cmp {
+            Some(cmp) => self.find_node(h, key, Some(&|k: &K| cmp(key, k))),
+            None => self.find_node(h, key, None),
+        }
+    }
     /// Finds or adds a node.
     /// Returns:
-    /// null if added
+    /// `None` if added, or the existing node together with the unused key
     pub(crate) unsafe fn put_tree_val(
         &mut self,
         h: usize,
-        key: *const K,
+        key: K,
         value: *mut V,
         guard: &Guard,
-    ) -> Option<&mut Node<K, V>> {
+    ) -> Option<(&mut Node<K, V>, K)> {
         let root = self.root;
         let mut p = root;
         let mut searched = false;
@@ -518,43 +572,46 @@ where
             let pd = &mut *(*p).node;
             let ph = pd.hash;
             let xp = p;
-            p = if ph > h {
-                (*p).left
+            let left = if ph > h {
+                true
             } else if ph < h {
-                (*p).right
-            } else if &*pd.key == &*key {
-                return Some(pd);
+                false
+            } else if *pd.key == key {
+                return Some((pd, key));
+            } else if let Some(cmp) = self.cmp {
+                match cmp(&key, &pd.key) {
+                    KeyOrdering::Less => true,
+                    KeyOrdering::Greater => false,
+                    KeyOrdering::Equal => return Some((pd, key)),
+                }
             } else {
-                if searched {
+                if !searched {
                     searched = true;
                     let ch = (*p).left;
                     if !ch.is_null() {
-                        if let Some(q) = (*ch).find_tree_node(h, &*key) {
-                            return Some(&mut *q.node);
+                        if let Some(q) = TreeNode::find_tree_node(ch, h, &key) {
+                            return Some((&mut *(*q).node, key));
                         }
                     }
                     let ch = (*p).right;
                     if !ch.is_null() {
-                        if let Some(q) = (*ch).find_tree_node(h, &*key) {
-                            return Some(&mut *q.node);
+                        if let Some(q) = TreeNode::find_tree_node(ch, h, &key) {
+                            return Some((&mut *(*q).node, key));
                         }
                     }
                 }
-                (*p).left
+                true
             };
+            p = if left { (*p).left } else { (*p).right };
             if p.is_null() {
                 let f = self.first.load(Ordering::Acquire);
                 let x = Node::new_next(h, key, value, f).into_box();
-                let shared = self.first.swap(x, Ordering::AcqRel);
-                if !shared.is_null() {
-                    guard.defer_destroy(shared);
-                }
-                let shared = (*f).prev.swap(x, Ordering::AcqRel);
-                if !shared.is_null() {
-                    guard.defer_destroy(shared);
+                self.first.store(x, Ordering::Release);
+                if let Some(f) = f.as_ref() {
+                    f.prev.store(x, Ordering::Release);
                 }
                 let x = TreeNode::new_parent(x, xp).into_box();
-                if ph >= h {
+                if left {
                     (*xp).left = x;
                 } else {
                     (*xp).right = x;
@@ -584,23 +641,18 @@ where
         let null = ptr::null_mut();
         let next = (*(*p).node).next.load(Ordering::Acquire);
         let prev = (*(*p).node).prev.load(Ordering::Acquire); // unlink traversal pointers
-        let (shared, is_null) = if let Some(prev) = prev.as_ref() {
-            (prev.next.swap(next, Ordering::AcqRel), false)
+        if let Some(prev) = prev.as_ref() {
+            prev.next.store(next, Ordering::Release);
         } else {
-            let is_null = next.is_null();
-            (self.first.swap(next, Ordering::AcqRel), is_null)
-        };
-        if !shared.is_null() {
-            guard.defer_destroy(shared);
-        }
-        if is_null {
-            return true;
+            self.first.store(next, Ordering::Release);
         }
         if let Some(next) = next.as_ref() {
-            let shared = next.prev.swap(prev, Ordering::AcqRel);
-            if !shared.is_null() {
-                guard.defer_destroy(shared);
-            }
+            next.prev.store(prev, Ordering::Release);
+        }
+        // readers walking `first` may still be standing on it
+        Node::retire((*p).node, guard);
+        if self.first.load(Ordering::Acquire).is_null() {
+            return true;
         }
         let mut r = self.root;
         let replacement;
@@ -627,9 +679,7 @@ where
                     s = sl;
                 }
                 // swap colors
-                let c = (*s).red;
-                (*s).red = (*p).red;
-                (*p).red = c;
+                mem::swap(&mut (*s).red, &mut (*p).red);
                 let sr = (*s).right;
                 let pp = (*p).parent;
                 if s == pr {
@@ -700,18 +750,139 @@ where
                 Self::balance_deletion(r, replacement)
             };
             if p == replacement {
+                // detach pointers
                 let pp = (*p).parent;
                 if !pp.is_null() {
                     if p == (*pp).left {
                         (*pp).left = null;
                     } else if p == (*pp).right {
                         (*pp).right = null;
-                        (*p).parent = null;
                     }
+                    (*p).parent = null;
                 }
             }
         }
         self.unlock_root();
+        // p is fully detached now, so dropping it does not touch the rest of the tree
+        guard.defer_destroy(p);
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::hash::{Hash, Hasher};
+
+    use crate::concurrent_hash_map::test_util::FewIds;
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap};
+
+    thread_local! {
+        static COMPARISONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// A key counting how often it is compared.
+    #[derive(Debug)]
+    struct Key(u32);
+
+    impl Hash for Key {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.hash(state);
+        }
+    }
+
+    impl PartialEq for Key {
+        fn eq(&self, other: &Self) -> bool {
+            COMPARISONS.with(|c| c.set(c.get() + 1));
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Key {}
+
+    impl PartialOrd for Key {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Key {
+        fn cmp(&self, other: &Self) -> Ordering {
+            COMPARISONS.with(|c| c.set(c.get() + 1));
+            self.0.cmp(&other.0)
+        }
+    }
+
+    fn comparisons(f: impl FnOnce()) -> usize {
+        COMPARISONS.with(|c| c.set(0));
+        f();
+        COMPARISONS.with(|c| c.get())
+    }
+
+    #[test]
+    fn ordered_bins_bound_lookups() {
+        const N: u32 = 1024;
+        let map = ConcurrentHashMap::with_ordered_bins_and_hasher(FewIds::<1>::default());
+        for i in 0..N {
+            map.insert(Key(i), i);
+        }
+        let view = map.ordered_view();
+        // a balanced tree of 1024 nodes is at most 20 levels deep
+        let bound = 2 * 20 + 2;
+        for i in (0..N).step_by(97) {
+            let n = comparisons(|| assert_eq!(view.get(&Key(i)).as_deref(), Some(&i)));
+            assert!(n <= bound, "get compared {n} keys");
+            let n = comparisons(|| assert!(view.contains_key(&Key(i))));
+            assert!(n <= bound, "contains_key compared {n} keys");
+            let n = comparisons(|| assert!(view.replace(&Key(i), &i, i + N)));
+            assert!(n <= bound, "replace compared {n} keys");
+            let n = comparisons(|| assert!(!view.remove_entry(&Key(i), &i)));
+            assert!(n <= bound, "remove_entry compared {n} keys");
+            let n = comparisons(|| assert_eq!(view.remove(&Key(i)).as_deref(), Some(&(i + N))));
+            assert!(n <= bound, "remove compared {n} keys");
+        }
+        let n = comparisons(|| assert!(view.get(&Key(N)).is_none()));
+        assert!(n <= bound, "a missing key compared {n} keys");
+        for i in 0..N {
+            assert_eq!(map.get(&Key(i)).is_some(), i % 97 != 0);
+        }
+    }
+
+    #[test]
+    fn ordered_view_bounds_borrowed_lookups() {
+        const N: u32 = 1024;
+        let map = ConcurrentHashMap::with_ordered_bins_and_hasher(FewIds::<1>::default());
+        for i in 0..N {
+            map.insert(Box::new(Key(i)), i);
+        }
+        let view = map.ordered_view();
+        let bound = 2 * 20 + 2;
+        for i in (0..N).step_by(97) {
+            let n = comparisons(|| assert_eq!(view.get(&Key(i)).as_deref(), Some(&i)));
+            assert!(n <= bound, "get by a borrowed key compared {n} keys");
+            let n = comparisons(|| assert_eq!(view.remove(&Key(i)).as_deref(), Some(&i)));
+            assert!(n <= bound, "remove by a borrowed key compared {n} keys");
+            // the map's own lookups scan the colliding keys, but still find them
+            assert!(map.get(&Key(i + 1)).is_some());
+            assert!(map.get(&Key(i)).is_none());
+        }
+    }
+
+    #[test]
+    fn unordered_bins_find_colliding_keys() {
+        let map = ConcurrentHashMap::with_hasher(FewIds::<1>::default());
+        for i in 0..256 {
+            map.insert(Key(i), i);
+        }
+        for i in 0..256 {
+            assert_eq!(map.get(&Key(i)).as_deref(), Some(&i));
+        }
+        for i in (0..256).step_by(2) {
+            assert_eq!(map.remove(&Key(i)).as_deref(), Some(&i));
+        }
+        for i in 0..256 {
+            assert_eq!(map.contains_key(&Key(i)), i % 2 == 1);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+
+use crate::concurrent_hash_map::base::ConcurrentHashMap;
+use crate::concurrent_hash_map::iter::{Iter, Keys, Values};
+use crate::concurrent_hash_map::map::{ConcurrentMap, Value};
+use crate::ebr::collector::Guard;
+
+/// A view of a map as a set of keys, in which additions may optionally be enabled by mapping
+/// to a common value. Removing a key from the view removes its mapping from the map.
+/// Created by `ConcurrentHashMap::key_set`.
+pub struct KeySetView<'m, K, V, S> {
+    map: &'m ConcurrentHashMap<K, V, S>,
+    // the value mapped to keys added through this view, if additions are enabled
+    value: Option<V>,
+}
+
+/// A view of the values of a map. Removing a value from the view removes one mapping to it
+/// from the map. Created by `ConcurrentHashMap::values_view`.
+pub struct ValuesView<'m, K, V, S> {
+    map: &'m ConcurrentHashMap<K, V, S>,
+}
+
+/// A view of the (key, value) entries of a map. Created by `ConcurrentHashMap::entry_set`.
+pub struct EntrySetView<'m, K, V, S> {
+    map: &'m ConcurrentHashMap<K, V, S>,
+}
+
+/// A view of a map with ordered keys, whose lookups and removals by a borrowed form of the
+/// key descend through the keys sharing a hash in ordered tree bins, instead of scanning them.
+/// Created by `ConcurrentHashMap::ordered_view`.
+pub struct OrderedView<'m, K, V, S> {
+    map: &'m ConcurrentHashMap<K, V, S>,
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns a set view of the keys of this map, without additions.
+    pub fn key_set(&self) -> KeySetView<'_, K, V, S> {
+        KeySetView {
+            map: self,
+            value: None,
+        }
+    }
+    /// Returns a view of the values of this map. To only iterate over them, see `values`.
+    pub fn values_view(&self) -> ValuesView<'_, K, V, S> {
+        ValuesView { map: self }
+    }
+    /// Returns a set view of the entries of this map.
+    pub fn entry_set(&self) -> EntrySetView<'_, K, V, S> {
+        EntrySetView { map: self }
+    }
+}
+
+impl<'m, K, V, S> KeySetView<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns this view with additions enabled, mapping every key added through it to
+    /// `value`.
+    pub fn with_default(self, value: V) -> Self {
+        KeySetView {
+            map: self.map,
+            value: Some(value),
+        }
+    }
+    /// Returns the map backing this view.
+    pub fn map(&self) -> &'m ConcurrentHashMap<K, V, S> {
+        self.map
+    }
+    /// Returns the default mapped value for additions, or `None` if additions are not
+    /// supported.
+    pub fn mapped_value(&self) -> Option<&V> {
+        self.value.as_ref()
+    }
+    /// Returns the number of keys in the map.
+    pub fn len(&self) -> usize {
+        self.map.size()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns true if the map contains `key`.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.contains_key(key)
+    }
+    /// Removes the key from this map view, by removing the key (and its corresponding
+    /// value) from the backing map.
+    /// Returns true if the backing map contained the key.
+    pub fn remove<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.remove(key).is_some()
+    }
+    /// Returns an iterator over the keys of the map. See `ConcurrentHashMap::iter` for its
+    /// guarantees.
+    pub fn iter<'g>(&'g self, guard: &'g Guard<'_>) -> Keys<'g, K, V> {
+        self.map.keys(guard)
+    }
+}
+
+impl<'m, K, V, S> KeySetView<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Clone + Send + 'static,
+    S: BuildHasher,
+{
+    /// Adds the specified key to this set view by mapping it to the default mapped value in
+    /// the backing map, if not already present.
+    /// Returns true if the key was added.
+    /// Panics if the view was not created with a default value, see `with_default`.
+    pub fn add(&self, key: K) -> bool {
+        let value = self
+            .value
+            .clone()
+            .expect("key set view has no default value to add keys with");
+        self.map.put_if_absent(key, value).is_none()
+    }
+}
+
+impl<'m, K, V, S> ValuesView<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns the map backing this view.
+    pub fn map(&self) -> &'m ConcurrentHashMap<K, V, S> {
+        self.map
+    }
+    /// Returns the number of values in the map.
+    pub fn len(&self) -> usize {
+        self.map.size()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns an iterator over the values of the map. See `ConcurrentHashMap::iter` for its
+    /// guarantees.
+    pub fn iter<'g>(&'g self, guard: &'g Guard<'_>) -> Values<'g, K, V> {
+        self.map.values(guard)
+    }
+}
+
+impl<'m, K, V, S> ValuesView<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: PartialEq + Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns true if some key of the map is mapped to `value`. This requires a traversal of
+    /// the whole map.
+    pub fn contains(&self, value: &V) -> bool {
+        let guard = self.map.guard();
+        let found = self.iter(&guard).any(|v| v == value);
+        found
+    }
+    /// Removes one mapping to `value` from the map, if any.
+    /// Returns true if a mapping was removed.
+    pub fn remove(&self, value: &V) -> bool {
+        let guard = self.map.guard();
+        for (k, v) in self.map.iter(&guard) {
+            if v == value && self.map.remove_entry(k, value) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<'m, K, V, S> EntrySetView<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns the map backing this view.
+    pub fn map(&self) -> &'m ConcurrentHashMap<K, V, S> {
+        self.map
+    }
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.size()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Adds the entry to the map, replacing the value of an existing one.
+    /// Returns true if the key was not present.
+    pub fn add(&self, key: K, value: V) -> bool {
+        self.map.insert(key, value).is_none()
+    }
+    /// Returns an iterator over the entries of the map. See `ConcurrentHashMap::iter` for its
+    /// guarantees.
+    pub fn iter<'g>(&'g self, guard: &'g Guard<'_>) -> Iter<'g, K, V> {
+        self.map.iter(guard)
+    }
+}
+
+impl<'m, K, V, S> EntrySetView<'m, K, V, S>
+where
+    K: Hash + Eq + Send + 'static,
+    V: PartialEq + Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns true if `key` is mapped to `value` in the map.
+    pub fn contains<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.get(key).is_some_and(|v| *v == *value)
+    }
+    /// Removes the entry for `key` only if it is mapped to `value`.
+    /// Returns true if the entry was removed.
+    pub fn remove<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.remove_entry(key, value)
+    }
+}
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+    K: Ord + Hash + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns a view of this map whose lookups use the key order in ordered tree bins, see
+    /// `with_ordered_bins_and_hasher`. On other maps they behave as the map's own.
+    pub fn ordered_view(&self) -> OrderedView<'_, K, V, S> {
+        OrderedView { map: self }
+    }
+}
+
+impl<'m, K, V, S> OrderedView<'m, K, V, S>
+where
+    K: Ord + Hash + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher,
+{
+    /// Returns the map backing this view.
+    pub fn map(&self) -> &'m ConcurrentHashMap<K, V, S> {
+        self.map
+    }
+    /// Returns the value mapped to `key`, see `ConcurrentMap::get`.
+    pub fn get<Q>(&self, key: &Q) -> Option<Value<'m, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        let guard = self.map.guard();
+        let ord = |k: &K| key.cmp(k.borrow());
+        unsafe { self.map.find(key, Some(&ord)) }.map(|v| Value::new(guard, v))
+    }
+    /// Returns true if the map contains `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        self.get(key).is_some()
+    }
+    /// Removes the mapping for `key`, see `ConcurrentMap::remove`.
+    pub fn remove<Q>(&self, key: &Q) -> Option<Value<'m, V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        self.replace_node(key, None, |_| true)
+            .map(|(guard, old)| Value::new_drop(guard, old))
+    }
+    /// Replaces node value with `value` if `cv` matches, or removes the node if `value` is
+    /// `None`, returning the guard that must retire the old value.
+    fn replace_node<Q, F>(&self, key: &Q, value: Option<V>, cv: F) -> Option<(Guard<'m>, *mut V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+        F: Fn(&V) -> bool,
+    {
+        let guard = self.map.guard();
+        let ord = |k: &K| key.cmp(k.borrow());
+        let old = unsafe { self.map.replace_node(key, Some(&ord), value, cv, &guard) }?;
+        Some((guard, old))
+    }
+}
+
+impl<'m, K, V, S> OrderedView<'m, K, V, S>
+where
+    K: Ord + Hash + Send + 'static,
+    V: PartialEq + Send + 'static,
+    S: BuildHasher,
+{
+    /// Removes the entry for `key` only if it is mapped to `value`.
+    /// Returns true if the entry was removed.
+    pub fn remove_entry<Q>(&self, key: &Q, value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        self.replace_node(key, None, |v| v == value)
+            .map(|(guard, old)| unsafe { guard.defer_destroy(old) })
+            .is_some()
+    }
+    /// Replaces the entry for `key` only if it is mapped to `old_value`.
+    /// Returns true if the value was replaced.
+    pub fn replace<Q>(&self, key: &Q, old_value: &V, new_value: V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Ord,
+    {
+        self.replace_node(key, Some(new_value), |v| v == old_value)
+            .map(|(guard, old)| unsafe { guard.defer_destroy(old) })
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrent_hash_map::{ConcurrentHashMap, ConcurrentMap};
+
+    #[test]
+    fn values_and_values_view() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i % 10);
+        }
+        let guard = map.guard();
+        let mut values: Vec<_> = map.values(&guard).copied().collect();
+        let view = map.values_view();
+        let mut seen: Vec<_> = view.iter(&guard).copied().collect();
+        values.sort_unstable();
+        seen.sort_unstable();
+        assert_eq!(values, seen);
+        assert_eq!(view.len(), 100);
+        assert!(view.contains(&3));
+        for _ in 0..10 {
+            assert!(view.remove(&3));
+        }
+        assert!(!view.remove(&3));
+        assert!(!map.values(&guard).any(|v| *v == 3));
+        assert_eq!(map.size(), 90);
+    }
+
+    #[test]
+    fn key_set_adds_with_default() {
+        let map = ConcurrentHashMap::new();
+        map.insert(1, 10);
+        let keys = map.key_set().with_default(7);
+        assert_eq!(keys.mapped_value(), Some(&7));
+        assert!(keys.add(2));
+        assert!(!keys.add(1));
+        assert_eq!(keys.len(), 2);
+        assert_eq!(map.get(&1).as_deref(), Some(&10));
+        assert_eq!(map.get(&2).as_deref(), Some(&7));
+    }
+
+    #[test]
+    #[should_panic(expected = "no default value")]
+    fn key_set_add_without_default() {
+        let map = ConcurrentHashMap::<u32, u32>::new();
+        map.key_set().add(1);
+    }
+}
@@ -5,11 +5,13 @@ use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
 
 thread_local! {
     static THREAD_ID:u64 = unsafe { mem::transmute(thread::current().id()) };
-    static GC_COUNT:RefCell<u64> = RefCell::new(0);
+    static GC_COUNT:RefCell<u64> = const { RefCell::new(0) };
 }
 const LEN: usize = 1 << 12;
 const RETIRE_LEN: usize = 1 << 8;
 const HASH_BITS: usize = LEN - 1;
+/// How many guards a thread drops between attempts to advance the epoch, by default.
+const GC_INTERVAL: u64 = 1 << 10;
 
 pub struct Collector {
     active_array: Vec<AtomicBool>,
@@ -17,6 +19,8 @@ pub struct Collector {
     global_epoch: AtomicUsize,
     retire_list: Vec<AtomicPtr<Collectible>>,
     state: AtomicBool,
+    // GC_COUNT values at which a dropped guard calls try_gc, one in every `gc_mask + 1`
+    gc_mask: u64,
 }
 /// Number of words a piece of `Data` can hold.
 ///
@@ -81,8 +85,25 @@ impl Collectible {
     }
 }
 
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Collector {
     pub fn new() -> Self {
+        Self::with_gc_interval(GC_INTERVAL)
+    }
+    /// Creates a collector that tries to advance the epoch, and free what was retired, once
+    /// every `interval` guards dropped by a thread. Smaller intervals free memory sooner, at
+    /// the cost of scanning the pinned guards more often.
+    /// Panics if `interval` is not a power of two.
+    pub fn with_gc_interval(interval: u64) -> Self {
+        assert!(
+            interval.is_power_of_two(),
+            "gc interval must be a power of two"
+        );
         let mut active_array = Vec::with_capacity(LEN);
         let mut epoch_array = Vec::with_capacity(LEN);
         let mut retire_list = Vec::with_capacity(RETIRE_LEN);
@@ -99,9 +120,10 @@ impl Collector {
             global_epoch: Default::default(),
             retire_list,
             state: Default::default(),
+            gc_mask: interval - 1,
         }
     }
-    pub fn pin(&self) -> Guard {
+    pub fn pin(&self) -> Guard<'_> {
         let thread_id: u64 = THREAD_ID.with(|f| *f);
         let mut index = HASH_BITS & thread_id as usize;
         while self.active_array[index]
@@ -114,7 +136,7 @@ impl Collector {
         let global_epoch = self.global_epoch.load(Ordering::Relaxed);
         self.epoch_array[index].store(global_epoch, Ordering::Release);
         Guard {
-            collector: &self,
+            collector: self,
             epoch: global_epoch,
             index,
         }
@@ -159,6 +181,15 @@ impl Collector {
     }
 }
 
+impl Drop for Collector {
+    fn drop(&mut self) {
+        // guards borrow the collector, so nothing retired can still be observed
+        for epoch in 0..RETIRE_LEN {
+            unsafe { self.free(epoch) };
+        }
+    }
+}
+
 pub struct Guard<'a> {
     collector: &'a Collector,
     epoch: usize,
@@ -175,19 +206,34 @@ impl<'a> Drop for Guard<'a> {
             *f.borrow_mut() = c;
             c
         });
-        if count & 0x3FF == 0x3FF {
+        let gc_mask = self.collector.gc_mask;
+        if count & gc_mask == gc_mask {
             self.collector.try_gc();
         }
     }
 }
 
 impl<'a> Guard<'a> {
+    /// Returns the collector this guard is pinned to.
+    pub(crate) fn collector(&self) -> &'a Collector {
+        self.collector
+    }
     pub fn unpin(self) {
         drop(self);
     }
-    pub fn defer_destroy<T>(&self, p: *mut T) {
-        unsafe { self.defer_unchecked(move || drop(Box::from_raw(p))) }
+    /// Defers dropping the box behind `p` until no pinned guard can still observe it.
+    ///
+    /// # Safety
+    /// `p` must come from `Box::into_raw`, must already be unreachable for threads that pin
+    /// after this call, and must not be retired twice.
+    pub unsafe fn defer_destroy<T>(&self, p: *mut T) {
+        self.defer_unchecked(move || drop(Box::from_raw(p)))
     }
+    /// Defers running `f` until no pinned guard can still observe the data it releases.
+    ///
+    /// # Safety
+    /// `f` runs on an arbitrary thread at an arbitrary later time, so everything it touches
+    /// must stay valid until then.
     pub unsafe fn defer_unchecked<F>(&self, f: F)
     where
         F: FnOnce(),
@@ -198,7 +244,10 @@ impl<'a> Guard<'a> {
         let next = &(*c_p).next;
         let epoch = self.epoch;
         let hash = epoch.wrapping_add(self.index) & (RETIRE_LEN - 1);
-        let hd = if hash == epoch.wrapping_sub(1) || hash == epoch.wrapping_add(1) {
+        // The list for `e` is freed once the global epoch reaches `e - 1`. A guard pinned at
+        // `epoch` may still see the global epoch move to `epoch + 2`, so the next three lists
+        // are never safe to use.
+        let hd = if (1..=3).contains(&(hash.wrapping_sub(epoch) & (RETIRE_LEN - 1))) {
             &self.collector.retire_list[epoch]
         } else {
             &self.collector.retire_list[hash]
@@ -208,10 +257,45 @@ impl<'a> Guard<'a> {
             if !p.is_null() {
                 next.store(p, Ordering::Relaxed);
             }
-            match hd.compare_exchange(p, c_p, Ordering::Relaxed, Ordering::Relaxed) {
-                Ok(_) => break,
-                Err(_) => {}
+            if hd
+                .compare_exchange(p, c_p, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
             }
         }
     }
 }
+
+/// How memory that was unlinked from a shared structure is given back.
+pub(crate) trait Reclaim {
+    /// # Safety
+    /// Same contract as `Guard::defer_destroy`.
+    unsafe fn reclaim<T>(&self, p: *mut T);
+    /// Runs `f`, which gives back memory unlinked from a shared structure.
+    ///
+    /// # Safety
+    /// Same contract as `Guard::defer_unchecked`.
+    unsafe fn reclaim_with<F: FnOnce()>(&self, f: F);
+}
+
+impl<'a> Reclaim for Guard<'a> {
+    unsafe fn reclaim<T>(&self, p: *mut T) {
+        self.defer_destroy(p)
+    }
+    unsafe fn reclaim_with<F: FnOnce()>(&self, f: F) {
+        self.defer_unchecked(f)
+    }
+}
+
+/// Frees memory right away, for owners with exclusive access that no reader can race with.
+pub(crate) struct Unprotected;
+
+impl Reclaim for Unprotected {
+    unsafe fn reclaim<T>(&self, p: *mut T) {
+        drop(Box::from_raw(p))
+    }
+    unsafe fn reclaim_with<F: FnOnce()>(&self, f: F) {
+        f()
+    }
+}
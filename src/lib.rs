@@ -1,2 +1,2 @@
-mod concurrent_hash_map;
+pub mod concurrent_hash_map;
 pub mod ebr;